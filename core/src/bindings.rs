@@ -0,0 +1,277 @@
+// Which physical key drives each key drawn on the visualizer. The monitor
+// process tracks every `PhysicalKey`; the UI draws a fixed set of slots and
+// looks up the key bound to each one.
+
+use crate::KeyStates;
+use crate::config::BindingsConfig;
+use crate::keys::PhysicalKey;
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Slot {
+    Pause,
+    // The two keys between ESC and the bomb key, drawn greyed out
+    Extra1,
+    Retry,
+    Extra2,
+    Bomb,
+    Shot,
+    Left,
+    Up,
+    Down,
+    Right,
+    Focus,
+    // The optional modifier row
+    ShiftLeft,
+    ShiftRight,
+    ControlLeft,
+    ControlRight,
+    AltLeft,
+    AltRight,
+    MetaLeft,
+    MetaRight,
+}
+
+impl Slot {
+    pub const COUNT: usize = 19;
+
+    // Order learn mode asks for keys in. The extra and modifier slots have no
+    // action and can only be bound in the config file.
+    pub const LEARN_ORDER: [Slot; 9] = [
+        Slot::Shot,
+        Slot::Bomb,
+        Slot::Focus,
+        Slot::Left,
+        Slot::Up,
+        Slot::Down,
+        Slot::Right,
+        Slot::Pause,
+        Slot::Retry,
+    ];
+
+    const ALL: [Slot; Slot::COUNT] = [
+        Slot::Pause,
+        Slot::Extra1,
+        Slot::Retry,
+        Slot::Extra2,
+        Slot::Bomb,
+        Slot::Shot,
+        Slot::Left,
+        Slot::Up,
+        Slot::Down,
+        Slot::Right,
+        Slot::Focus,
+        Slot::ShiftLeft,
+        Slot::ShiftRight,
+        Slot::ControlLeft,
+        Slot::ControlRight,
+        Slot::AltLeft,
+        Slot::AltRight,
+        Slot::MetaLeft,
+        Slot::MetaRight,
+    ];
+
+    // Key name in `[bindings]`
+    pub fn name(self) -> &'static str {
+        match self {
+            Slot::Pause => "pause",
+            Slot::Extra1 => "extra1",
+            Slot::Retry => "retry",
+            Slot::Extra2 => "extra2",
+            Slot::Bomb => "bomb",
+            Slot::Shot => "shot",
+            Slot::Left => "left",
+            Slot::Up => "up",
+            Slot::Down => "down",
+            Slot::Right => "right",
+            Slot::Focus => "focus",
+            Slot::ShiftLeft => "shift-left",
+            Slot::ShiftRight => "shift-right",
+            Slot::ControlLeft => "control-left",
+            Slot::ControlRight => "control-right",
+            Slot::AltLeft => "alt-left",
+            Slot::AltRight => "alt-right",
+            Slot::MetaLeft => "meta-left",
+            Slot::MetaRight => "meta-right",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Slot::ALL.into_iter().find(|slot| slot.name() == name)
+    }
+
+    // Game action shown with `[labels] main = "action"`; the extra and
+    // modifier slots have none
+    pub fn action(self) -> Option<&'static str> {
+        match self {
+            Slot::Pause => Some("PAUSE"),
+            Slot::Retry => Some("RETRY"),
+            Slot::Bomb => Some("BOMB"),
+            Slot::Shot => Some("SHOT"),
+            Slot::Left => Some("LEFT"),
+            Slot::Up => Some("UP"),
+            Slot::Down => Some("DOWN"),
+            Slot::Right => Some("RIGHT"),
+            Slot::Focus => Some("FOCUS"),
+            _ => None,
+        }
+    }
+
+    // The keys from SPECIFICATION.md
+    fn default_key(self) -> PhysicalKey {
+        match self {
+            Slot::Pause => PhysicalKey::Escape,
+            Slot::Extra1 => PhysicalKey::A,
+            Slot::Retry => PhysicalKey::S,
+            Slot::Extra2 => PhysicalKey::D,
+            Slot::Bomb => PhysicalKey::F,
+            Slot::Shot => PhysicalKey::Backspace,
+            Slot::Left => PhysicalKey::J,
+            Slot::Up => PhysicalKey::K,
+            Slot::Down => PhysicalKey::L,
+            Slot::Right => PhysicalKey::Semicolon,
+            Slot::Focus => PhysicalKey::Space,
+            Slot::ShiftLeft => PhysicalKey::ShiftLeft,
+            Slot::ShiftRight => PhysicalKey::ShiftRight,
+            Slot::ControlLeft => PhysicalKey::ControlLeft,
+            Slot::ControlRight => PhysicalKey::ControlRight,
+            Slot::AltLeft => PhysicalKey::AltLeft,
+            Slot::AltRight => PhysicalKey::AltRight,
+            Slot::MetaLeft => PhysicalKey::MetaLeft,
+            Slot::MetaRight => PhysicalKey::MetaRight,
+        }
+    }
+
+    fn configured(self, config: &BindingsConfig) -> Option<&str> {
+        match self {
+            Slot::Pause => config.pause.as_deref(),
+            Slot::Extra1 => config.extra1.as_deref(),
+            Slot::Retry => config.retry.as_deref(),
+            Slot::Extra2 => config.extra2.as_deref(),
+            Slot::Bomb => config.bomb.as_deref(),
+            Slot::Shot => config.shot.as_deref(),
+            Slot::Left => config.left.as_deref(),
+            Slot::Up => config.up.as_deref(),
+            Slot::Down => config.down.as_deref(),
+            Slot::Right => config.right.as_deref(),
+            Slot::Focus => config.focus.as_deref(),
+            Slot::ShiftLeft => config.shift_left.as_deref(),
+            Slot::ShiftRight => config.shift_right.as_deref(),
+            Slot::ControlLeft => config.control_left.as_deref(),
+            Slot::ControlRight => config.control_right.as_deref(),
+            Slot::AltLeft => config.alt_left.as_deref(),
+            Slot::AltRight => config.alt_right.as_deref(),
+            Slot::MetaLeft => config.meta_left.as_deref(),
+            Slot::MetaRight => config.meta_right.as_deref(),
+        }
+    }
+}
+
+// Lives in shared memory so learned bindings survive UI restarts
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Bindings {
+    keys: [PhysicalKey; Slot::COUNT],
+    // A slot bound to a modifier is also driven by the other side's key
+    pub merge_sides: bool,
+}
+
+impl Bindings {
+    pub fn new() -> Self {
+        Self {
+            keys: Slot::ALL.map(Slot::default_key),
+            merge_sides: false,
+        }
+    }
+
+    // Unknown key names keep the slot's default key
+    pub fn from_config(config: &BindingsConfig, merge_sides: bool) -> Self {
+        let mut bindings = Self::new();
+        bindings.merge_sides = merge_sides;
+        for slot in Slot::ALL {
+            let Some(name) = slot.configured(config) else {
+                continue;
+            };
+            match PhysicalKey::from_name(name) {
+                Some(key) => bindings.keys[slot as usize] = key,
+                None => {
+                    log::warn!(slot = slot.name(), key = name; "Unknown key in [bindings], using the default");
+                }
+            }
+        }
+        bindings
+    }
+
+    pub fn key(&self, slot: Slot) -> PhysicalKey {
+        self.keys[slot as usize]
+    }
+
+    pub fn is_pressed(&self, slot: Slot, key_states: &KeyStates) -> bool {
+        let key = self.key(slot);
+        let other_side = key.other_side().filter(|_| self.merge_sides);
+        key_states.get_key_state(key) || other_side.is_some_and(|key| key_states.get_key_state(key))
+    }
+
+    pub fn slot_for(&self, key: PhysicalKey) -> Option<Slot> {
+        Slot::ALL.into_iter().find(|&slot| self.key(slot) == key)
+    }
+
+    // A key drives at most one slot: a slot that had `key` before takes over
+    // the old key of `slot`
+    pub fn bind(&mut self, slot: Slot, key: PhysicalKey) {
+        let previous = self.keys[slot as usize];
+        for bound in &mut self.keys {
+            if *bound == key {
+                *bound = previous;
+            }
+        }
+        self.keys[slot as usize] = key;
+    }
+
+    // A `slot = "key"` line per slot, the body of the table learn mode writes
+    pub fn to_toml(self) -> String {
+        let mut text = String::new();
+        for slot in Slot::ALL {
+            // Quoted and escaped by toml, e.g. "\\" for the backslash key
+            let name = toml::Value::String(self.key(slot).name().to_string());
+            text.push_str(&format!("{} = {}\n", slot.name(), name));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binding_a_taken_key_swaps() {
+        let mut bindings = Bindings::new();
+        let shot = bindings.key(Slot::Shot);
+        let bomb = bindings.key(Slot::Bomb);
+
+        bindings.bind(Slot::Shot, bomb);
+        assert_eq!(bindings.key(Slot::Shot), bomb);
+        assert_eq!(bindings.key(Slot::Bomb), shot);
+        assert_eq!(bindings.slot_for(shot), Some(Slot::Bomb));
+
+        // A free key leaves the other slots alone
+        bindings.bind(Slot::Shot, PhysicalKey::Z);
+        assert_eq!(bindings.key(Slot::Shot), PhysicalKey::Z);
+        assert_eq!(bindings.key(Slot::Bomb), shot);
+        assert_eq!(bindings.slot_for(bomb), None);
+    }
+
+    #[test]
+    fn to_toml_reads_back() {
+        let mut bindings = Bindings::new();
+        bindings.bind(Slot::Shot, PhysicalKey::Backslash);
+        bindings.bind(Slot::Focus, PhysicalKey::Quote);
+
+        let config: crate::config::BindingsConfig = toml::from_str(&bindings.to_toml()).unwrap();
+        let read_back = Bindings::from_config(&config, false);
+        for slot in Slot::ALL {
+            assert_eq!(read_back.key(slot), bindings.key(slot), "{}", slot.name());
+        }
+    }
+}
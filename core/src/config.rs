@@ -0,0 +1,828 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// User configuration, read once at startup (before fork) so both processes
+// see the same values. Every field has a default, so the file is optional and
+// may contain only the settings someone wants to change.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub frame: FrameConfig,
+    pub input: InputConfig,
+    pub bindings: BindingsConfig,
+    pub modifiers: ModifierConfig,
+    pub labels: LabelConfig,
+    pub layout: LayoutConfig,
+    pub remap: RemapConfig,
+    pub icons: IconConfig,
+    pub theme: ThemeConfig,
+    pub xkb: XkbConfig,
+    pub log: LogConfig,
+    // The `[profiles.<name>]` laid over the rest of the file, if any
+    #[serde(skip)]
+    pub profile: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum PacingMode {
+    // Redraw continuously at a multiple of the monitor refresh rate
+    FixedMultiple,
+    // Let the buffer swap block on the display's vertical sync
+    Vsync,
+    // Sleep until the monitor process reports a key change
+    EventDriven,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FrameConfig {
+    pub pacing: PacingMode,
+    // Used by `fixed-multiple`: a fixed target FPS that ignores the monitor
+    pub target_fps: Option<u32>,
+    // Used by `fixed-multiple`: target FPS = refresh rate * multiplier
+    pub refresh_multiplier: u32,
+    // Assumed refresh rate when the monitor reports 0 Hz (Xvfb, some virtual
+    // displays and remote sessions)
+    pub fallback_refresh_rate: u32,
+    // Used by `event-driven`: redraw at least this often for mouse hover,
+    // permission changes and layout updates
+    pub idle_redraw_ms: u64,
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self {
+            pacing: PacingMode::FixedMultiple,
+            target_fps: None,
+            refresh_multiplier: 2,
+            fallback_refresh_rate: 60,
+            idle_redraw_ms: 100,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputBackend {
+    // evdev on Linux when /dev/input is readable, rdev otherwise
+    Auto,
+    // Global hook through rdev (X11 on Linux)
+    Rdev,
+    // Linux only: read /dev/input/event* directly, works under Wayland
+    Evdev,
+    // Play `replay_file` in a loop instead of listening to a keyboard
+    Replay,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    pub backend: InputBackend,
+    // Keyboards that feed the visualizer; empty means all of them. Needs the
+    // evdev backend, rdev can't tell devices apart.
+    pub devices: Vec<DeviceMatch>,
+    // Script for the `replay` backend (see input.rs for the format)
+    pub replay_file: Option<PathBuf>,
+    // Mark keys that are autorepeating instead of dropping repeats
+    pub show_repeats: bool,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            backend: InputBackend::Auto,
+            devices: Vec::new(),
+            replay_file: None,
+            show_repeats: false,
+        }
+    }
+}
+
+// A device matches when every field that is set matches
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceMatch {
+    // Case-insensitive substring of the device name
+    pub name: Option<String>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+    // Event node, or a symlink to one such as /dev/input/by-id/...
+    pub path: Option<PathBuf>,
+}
+
+// Key name (see keys.rs) per slot; unset slots keep the keys from
+// SPECIFICATION.md. Learn mode rewrites this table, or the selected
+// profile's.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct BindingsConfig {
+    pub pause: Option<String>,
+    pub extra1: Option<String>,
+    pub retry: Option<String>,
+    pub extra2: Option<String>,
+    pub bomb: Option<String>,
+    pub shot: Option<String>,
+    pub left: Option<String>,
+    pub up: Option<String>,
+    pub down: Option<String>,
+    pub right: Option<String>,
+    pub focus: Option<String>,
+    #[serde(rename = "shift-left")]
+    pub shift_left: Option<String>,
+    #[serde(rename = "shift-right")]
+    pub shift_right: Option<String>,
+    #[serde(rename = "control-left")]
+    pub control_left: Option<String>,
+    #[serde(rename = "control-right")]
+    pub control_right: Option<String>,
+    #[serde(rename = "alt-left")]
+    pub alt_left: Option<String>,
+    #[serde(rename = "alt-right")]
+    pub alt_right: Option<String>,
+    #[serde(rename = "meta-left")]
+    pub meta_left: Option<String>,
+    #[serde(rename = "meta-right")]
+    pub meta_right: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModifierConfig {
+    // Draw a row of Shift/Control/Alt/Meta keys under the keyboard
+    pub show: bool,
+    // Treat the left and right key of each modifier as one key, both in that
+    // row and for any slot bound to a modifier
+    pub merge_sides: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum LabelSource {
+    // What the key types in the active layout, or its name for keys that
+    // don't type anything
+    Layout,
+    // The key's name on a US QWERTY keyboard, whatever the layout
+    Qwerty,
+    // The slot's game action: SHOT, BOMB, ...
+    Action,
+    // The slot's entry in `[labels.custom]`
+    Custom,
+    // Nothing
+    None,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LabelConfig {
+    // Big label in the middle of the key; keys that have nothing for it
+    // (e.g. no action) show the layout label instead
+    pub main: LabelSource,
+    // Small label in the top-left corner, shown when it differs from the
+    // main label
+    pub hint: LabelSource,
+    // Slot name (see bindings.rs) -> text, for `custom`
+    pub custom: BTreeMap<String, String>,
+    // Print the Shift and AltGr characters in the key's corners, like keycap
+    // legends
+    pub legends: bool,
+    // Show the Shift layer as the main label while a Shift key is held
+    pub follow_shift: bool,
+}
+
+impl Default for LabelConfig {
+    fn default() -> Self {
+        Self {
+            main: LabelSource::Layout,
+            hint: LabelSource::Qwerty,
+            custom: BTreeMap::new(),
+            legends: true,
+            follow_shift: false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutConfig {
+    // How long "Switched to ..." stays on screen after a layout switch; 0
+    // turns it off
+    pub toast_ms: u64,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self { toast_ms: 2000 }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RemapConfig {
+    // Start with remap mode on (the "Remap" button toggles it)
+    pub enabled: bool,
+    // Key name (see keys.rs) -> what a key remapper turns it into; unset
+    // keeps the table from the legacy version
+    pub keys: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IconConfig {
+    // Directory with an `icons.toml` manifest (see icons.rs)
+    pub pack: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyStyle {
+    // Text labels with a small icon
+    Labeled,
+    // Keys that have an icon show only that, large and centered, for a
+    // minimal overlay
+    IconOnly,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum IconPosition {
+    Top,
+    Center,
+    Bottom,
+}
+
+// Unset fields keep the state's default
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IconStyleConfig {
+    // RGBA
+    pub tint: Option<[u8; 4]>,
+    // In window coordinates
+    pub size: Option<f32>,
+    pub position: Option<IconPosition>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IconThemeConfig {
+    pub normal: IconStyleConfig,
+    pub pressed: IconStyleConfig,
+    // Without input permission
+    pub disabled: IconStyleConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub key_style: KeyStyle,
+    // Icon size for `icon-only` keys
+    pub icon_only_size: f32,
+    pub icon: IconThemeConfig,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        Self {
+            key_style: KeyStyle::Labeled,
+            icon_only_size: 32.0,
+            icon: IconThemeConfig::default(),
+        }
+    }
+}
+
+// Linux only: the keymap that names the layout and labels the keys. Unset
+// fields fall back to XKB_DEFAULT_* and then to xkbcommon's defaults.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct XkbConfig {
+    pub rules: Option<String>,
+    pub model: Option<String>,
+    // Comma-separated for several layouts, e.g. "us,de"
+    pub layout: Option<String>,
+    pub variant: Option<String>,
+    // e.g. "grp:alt_shift_toggle" to switch between the layouts
+    pub options: Option<String>,
+    // A compiled keymap (`xkbcomp -xkb`) used instead of the names above
+    pub keymap_file: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    // error, warn, info, debug or trace (`THKEYVIS_LOG` overrides this)
+    pub level: String,
+    // Optional log file shared by both processes, rotated by size
+    pub file: Option<PathBuf>,
+    pub max_file_kb: u64,
+    // Number of rotated files kept next to the current one
+    pub max_files: u32,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            file: None,
+            max_file_kb: 1024,
+            max_files: 3,
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Self {
+        let Some(path) = config_path() else {
+            return Self::default();
+        };
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            // No config file is the normal case
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(error) => {
+                log::error!(path:% = path.display(), error:% = error; "Failed to read config");
+                return Self::default();
+            }
+        };
+
+        let profile = std::env::var("THKEYVIS_PROFILE").ok();
+        match parse(&text, profile.as_deref()) {
+            Ok(config) => {
+                log::info!(path:% = path.display(), profile:? = config.profile; "Loaded config");
+                config
+            }
+            Err(error) => {
+                log::error!(path:% = path.display(), error:% = error; "Invalid config, using defaults");
+                Self::default()
+            }
+        }
+    }
+}
+
+// Profiles are named sets of settings, e.g. actions for a stream overlay and
+// keys for practice. `profile = "<name>"` (or `THKEYVIS_PROFILE`) lays
+// `[profiles.<name>]` over the rest of the file: its tables are merged into
+// the top-level ones, anything else replaces them.
+fn parse(text: &str, profile_override: Option<&str>) -> Result<Config, String> {
+    let mut table: toml::Table = toml::from_str(text).map_err(|error| error.to_string())?;
+    let profile = match (profile_override, table.remove("profile")) {
+        (Some(name), _) => Some(name.to_string()),
+        (None, Some(toml::Value::String(name))) => Some(name),
+        (None, Some(_)) => return Err("`profile` must be a string".to_string()),
+        (None, None) => None,
+    };
+    let profiles = match table.remove("profiles") {
+        Some(toml::Value::Table(profiles)) => profiles,
+        Some(_) => return Err("`profiles` must be a table".to_string()),
+        None => toml::Table::new(),
+    };
+
+    // Check every profile, not only the selected one, so a typo shows up
+    // before the profile is used
+    let mut selected = None;
+    for (name, settings) in profiles {
+        let toml::Value::Table(settings) = settings else {
+            return Err(format!("[profiles.{name}] must be a table"));
+        };
+        let mut merged = table.clone();
+        merge(&mut merged, settings);
+        let config: Config = merged
+            .try_into()
+            .map_err(|error| format!("[profiles.{name}]: {error}"))?;
+        if profile.as_deref() == Some(name.as_str()) {
+            selected = Some(config);
+        }
+    }
+
+    let mut config = match (&profile, selected) {
+        (None, _) => table.try_into().map_err(|error| error.to_string())?,
+        (Some(_), Some(config)) => config,
+        (Some(name), None) => return Err(format!("no [profiles.{name}] table")),
+    };
+    config.profile = profile;
+    Ok(config)
+}
+
+fn merge(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(over)) => merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+// Replace the learned bindings in the config file at `path` with `entries`
+// (`slot = "key"` lines), creating the file if needed. They go to the
+// selected profile's `[profiles.<name>.bindings]`, which would win over the
+// top-level `[bindings]`, or to `[bindings]` without a profile. The rest of
+// the file, comments included, is kept as is.
+pub fn save_bindings(path: &Path, profile: Option<&str>, entries: &str) -> std::io::Result<()> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(error),
+    };
+
+    // Headers are matched without their quotes, see `table_header`
+    let (name, header) = match profile {
+        Some(profile) => (
+            format!("profiles.{}.bindings", profile),
+            format!("[profiles.{}.bindings]", toml_key(profile)),
+        ),
+        None => ("bindings".to_string(), "[bindings]".to_string()),
+    };
+    let mut text = without_table(&text, &name).trim_end().to_string();
+    if !text.is_empty() {
+        text.push_str("\n\n");
+    }
+    text.push_str(&header);
+    text.push('\n');
+    text.push_str(entries);
+
+    // Rather fail than write a file the next start can't read
+    if let Err(error) = toml::from_str::<toml::Table>(&text) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "the config file would not be valid TOML: {}",
+                error.message()
+            ),
+        ));
+    }
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // A crash halfway through leaves the old file, not half of the new one
+    let temp_path = path.with_extension("toml.tmp");
+    std::fs::write(&temp_path, text)?;
+    std::fs::rename(&temp_path, path)
+}
+
+// `name` bare if it can be, quoted otherwise
+fn toml_key(name: &str) -> String {
+    let bare = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if bare {
+        name.to_string()
+    } else {
+        toml::Value::String(name.to_string()).to_string()
+    }
+}
+
+// `text` without the table `[name]`: its header and every line up to the
+// next header. Only brackets that start a line outside of strings and
+// multi-line arrays or inline tables are headers.
+fn without_table(text: &str, name: &str) -> String {
+    let mut kept = String::new();
+    let mut scanner = TomlScanner::default();
+    let mut in_table = false;
+    for line in text.lines() {
+        if scanner.at_top_level()
+            && let Some(header) = table_header(line)
+        {
+            in_table = header == name;
+        }
+        scanner.feed(line);
+        if !in_table {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    kept
+}
+
+// The table name of a `[table]` or `[[array]]` header line, without quotes
+// and spaces around its parts; arrays of tables get their brackets back so
+// they never match a table name
+fn table_header(line: &str) -> Option<String> {
+    let line = line.trim_start();
+    let (inner, is_array) = match line.strip_prefix("[[") {
+        Some(rest) => (rest, true),
+        None => (line.strip_prefix('[')?, false),
+    };
+    let name = inner[..inner.find(']')?]
+        .split('.')
+        .map(|part| part.trim().trim_matches('"').trim_matches('\''))
+        .collect::<Vec<_>>()
+        .join(".");
+    Some(if is_array {
+        format!("[[{}]]", name)
+    } else {
+        name
+    })
+}
+
+// Follows strings, comments and brackets line by line, just enough to tell
+// where a line starts
+#[derive(Default)]
+struct TomlScanner {
+    // Open `[` and `{` of values
+    depth: usize,
+    // The closing delimiter of an open multi-line string
+    multi_line_string: Option<&'static str>,
+}
+
+impl TomlScanner {
+    fn at_top_level(&self) -> bool {
+        self.depth == 0 && self.multi_line_string.is_none()
+    }
+
+    fn feed(&mut self, line: &str) {
+        let mut rest = line;
+        while !rest.is_empty() {
+            if let Some(end) = self.multi_line_string {
+                let Some(close) = find_unescaped(rest, end) else {
+                    return;
+                };
+                rest = &rest[close + end.len()..];
+                self.multi_line_string = None;
+                continue;
+            }
+
+            let Some(c) = rest.chars().next() else {
+                return;
+            };
+            match c {
+                '#' => return,
+                '[' | '{' => self.depth += 1,
+                ']' | '}' => self.depth = self.depth.saturating_sub(1),
+                '"' | '\'' => {
+                    let quote = if c == '"' { "\"" } else { "'" };
+                    let triple = if c == '"' { "\"\"\"" } else { "'''" };
+                    if let Some(after) = rest.strip_prefix(triple) {
+                        self.multi_line_string = Some(triple);
+                        rest = after;
+                        continue;
+                    }
+                    // Single-line strings end on this line
+                    let after = &rest[1..];
+                    rest = match find_unescaped(after, quote) {
+                        Some(close) => &after[close + 1..],
+                        None => "",
+                    };
+                    continue;
+                }
+                _ => {}
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+}
+
+// Byte offset of `delimiter` in `text`, skipping backslash escapes for the
+// double-quoted strings that have them
+fn find_unescaped(text: &str, delimiter: &str) -> Option<usize> {
+    if delimiter.starts_with('\'') {
+        return text.find(delimiter);
+    }
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if text[index..].starts_with(delimiter) {
+            return Some(index);
+        }
+    }
+    None
+}
+
+// `THKEYVIS_CONFIG` overrides the platform default location
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("THKEYVIS_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    let home = PathBuf::from(std::env::var_os("HOME")?);
+    if cfg!(target_os = "macos") {
+        Some(home.join("Library/Application Support/THKeyVis/config.toml"))
+    } else {
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home.join(".config"));
+        Some(config_home.join("thkeyvis/config.toml"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"# THKeyVis settings
+[frame]
+pacing = "vsync" # comment with [brackets]
+
+[bindings]
+shot = "z"
+bomb = "x"
+
+[remap.keys]
+# a multi-line array whose lines start with brackets
+layers = [
+  ["a", "b"],
+  [ "c" ],
+]
+note = """
+[bindings]
+not a header
+"""
+
+[[input.devices]]
+name = "ErgoDox"
+"#;
+
+    #[test]
+    fn only_the_bindings_table_is_removed() {
+        let text = without_table(CONFIG, "bindings");
+        assert!(!text.contains("shot = \"z\""));
+        assert!(!text.contains("bomb"));
+        // Everything else, comments included, is kept
+        assert_eq!(
+            text,
+            CONFIG.replace("[bindings]\nshot = \"z\"\nbomb = \"x\"\n\n", "")
+        );
+    }
+
+    #[test]
+    fn headers_may_be_spaced_quoted_and_commented() {
+        let text = "[ \"bindings\" ] # learned\nshot = \"z\"\n[log]\nlevel = \"debug\"\n";
+        assert_eq!(
+            without_table(text, "bindings"),
+            "[log]\nlevel = \"debug\"\n"
+        );
+        // A table named like an array of tables is a different thing
+        let text = "[[bindings]]\nshot = \"z\"\n";
+        assert_eq!(without_table(text, "bindings"), text);
+    }
+
+    #[test]
+    fn saved_bindings_replace_the_old_ones() {
+        let dir = std::env::temp_dir().join(format!("thkeyvis-config-{}", std::process::id()));
+        let path = dir.join("config.toml");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, CONFIG).unwrap();
+
+        save_bindings(&path, None, "shot = \"backspace\"\n").unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(text.starts_with("# THKeyVis settings\n[frame]\n"));
+        assert!(text.contains("note = \"\"\"\n[bindings]\nnot a header\n\"\"\""));
+        assert!(text.ends_with("name = \"ErgoDox\"\n\n[bindings]\nshot = \"backspace\"\n"));
+        let table: toml::Table = toml::from_str(&text).unwrap();
+        assert_eq!(table["bindings"]["shot"].as_str(), Some("backspace"));
+        assert!(table["bindings"].get("bomb").is_none());
+    }
+
+    #[test]
+    fn bindings_are_saved_to_the_selected_profile() {
+        let dir = std::env::temp_dir().join(format!("thkeyvis-profile-{}", std::process::id()));
+        let path = dir.join("config.toml");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, CONFIG).unwrap();
+
+        save_bindings(&path, Some("stream"), "shot = \"x\"\n").unwrap();
+        // Saving again replaces the profile's table instead of adding one
+        save_bindings(&path, Some("stream"), "shot = \"c\"\n").unwrap();
+        save_bindings(&path, Some("my set"), "shot = \"v\"\n").unwrap();
+        save_bindings(&path, Some("my set"), "shot = \"b\"\n").unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // The top-level table is left alone
+        assert!(text.contains("[bindings]\nshot = \"z\"\nbomb = \"x\"\n"));
+        assert!(text.ends_with(
+            "[profiles.stream.bindings]\nshot = \"c\"\n\n[profiles.\"my set\".bindings]\nshot = \"b\"\n"
+        ));
+        let table: toml::Table = toml::from_str(&text).unwrap();
+        let profiles = &table["profiles"];
+        assert_eq!(profiles["stream"]["bindings"]["shot"].as_str(), Some("c"));
+        assert_eq!(profiles["my set"]["bindings"]["shot"].as_str(), Some("b"));
+    }
+
+    #[test]
+    fn invalid_result_is_not_written() {
+        let dir = std::env::temp_dir().join(format!("thkeyvis-invalid-{}", std::process::id()));
+        let path = dir.join("config.toml");
+        std::fs::create_dir_all(&dir).unwrap();
+        // A dotted key defines the table a second time
+        let config = "bindings.shot = \"z\"\n";
+        std::fs::write(&path, config).unwrap();
+
+        let result = save_bindings(&path, None, "shot = \"x\"\n");
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(text, config);
+    }
+
+    #[test]
+    fn theme_is_parsed() {
+        let config = parse(
+            r#"[theme]
+key_style = "icon-only"
+icon_only_size = 40
+
+[theme.icon.pressed]
+tint = [50, 80, 120, 230]
+size = 20
+position = "center"
+"#,
+            None,
+        )
+        .unwrap();
+        let theme = &config.theme;
+        assert_eq!(theme.key_style, KeyStyle::IconOnly);
+        assert_eq!(theme.icon_only_size, 40.0);
+        assert_eq!(theme.icon.pressed.tint, Some([50, 80, 120, 230]));
+        assert_eq!(theme.icon.pressed.size, Some(20.0));
+        assert_eq!(theme.icon.pressed.position, Some(IconPosition::Center));
+        assert_eq!(theme.icon.normal.tint, None);
+        assert_eq!(theme.icon.disabled.position, None);
+
+        let defaults = parse("", None).unwrap().theme;
+        assert_eq!(defaults.key_style, KeyStyle::Labeled);
+        assert_eq!(defaults.icon_only_size, 32.0);
+
+        for (text, error) in [
+            ("[theme]\nkey_style = \"tiny\"\n", "unknown variant `tiny`"),
+            (
+                "[theme.icon.normal]\nposition = \"left\"\n",
+                "unknown variant `left`",
+            ),
+            (
+                "[theme.icon.normal]\ntint = [1, 2, 3]\n",
+                "invalid length 3",
+            ),
+            ("[theme.icon.hover]\nsize = 10\n", "unknown field `hover`"),
+        ] {
+            let message = parse(text, None).err().unwrap();
+            assert!(message.contains(error), "{message}");
+        }
+    }
+
+    const PROFILES: &str = r#"profile = "stream"
+
+[labels]
+main = "qwerty"
+hint = "none"
+custom = { shot = "Z" }
+
+[frame]
+pacing = "vsync"
+
+[profiles.stream.labels]
+main = "action"
+
+[profiles.practice]
+labels.main = "layout"
+frame = { pacing = "event-driven" }
+"#;
+
+    #[test]
+    fn profile_is_laid_over_the_file() {
+        let config = parse(PROFILES, None).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("stream"));
+        assert_eq!(config.labels.main, LabelSource::Action);
+        // Settings the profile leaves out come from the rest of the file
+        assert_eq!(config.labels.hint, LabelSource::None);
+        assert_eq!(config.labels.custom["shot"], "Z");
+        assert_eq!(config.frame.pacing, PacingMode::Vsync);
+    }
+
+    #[test]
+    fn override_picks_another_profile() {
+        let config = parse(PROFILES, Some("practice")).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("practice"));
+        assert_eq!(config.labels.main, LabelSource::Layout);
+        assert_eq!(config.frame.pacing, PacingMode::EventDriven);
+
+        let without_selector = PROFILES.replace("profile = \"stream\"\n", "");
+        let config = parse(&without_selector, None).unwrap();
+        assert_eq!(config.profile, None);
+        assert_eq!(config.labels.main, LabelSource::Qwerty);
+    }
+
+    #[test]
+    fn profile_errors_are_reported() {
+        let error = parse(PROFILES, Some("missing")).err().unwrap();
+        assert_eq!(error, "no [profiles.missing] table");
+
+        // Unused profiles are checked too
+        let typo = format!("{PROFILES}\n[profiles.old.labels]\nmian = \"action\"\n");
+        let error = parse(&typo, None).err().unwrap();
+        assert!(
+            error.starts_with("[profiles.old]: unknown field `mian`"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,475 @@
+// Linux input backend that reads keyboards straight from /dev/input/event*.
+// rdev goes through X11 and sees nothing in Wayland sessions; the kernel
+// devices work under any display server. Devices are only read, never
+// grabbed (no EVIOCGRAB), so other applications keep receiving every key.
+
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::config::DeviceMatch;
+
+const INPUT_DIR: &str = "/dev/input";
+
+// From linux/input-event-codes.h
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const SYN_REPORT: u16 = 0;
+const SYN_DROPPED: u16 = 3;
+const KEY_A: usize = 30;
+const KEY_SPACE: usize = 57;
+const KEY_MAX: usize = 0x2ff;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyEvent {
+    // Linux key code (KEY_*)
+    pub code: u16,
+    pub pressed: bool,
+    // Kernel autorepeat of a held key
+    pub repeat: bool,
+}
+
+// Identifies the keyboard an event came from
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub path: PathBuf,
+    // Shared with every event from the device
+    pub name: Rc<str>,
+    pub vendor: u16,
+    pub product: u16,
+}
+
+impl DeviceInfo {
+    pub fn matches(&self, rule: &DeviceMatch) -> bool {
+        let name_matches = rule
+            .name
+            .as_ref()
+            .is_none_or(|name| self.name.to_lowercase().contains(&name.to_lowercase()));
+        let path_matches = rule
+            .path
+            .as_ref()
+            .is_none_or(|path| std::fs::canonicalize(path).is_ok_and(|path| path == self.path));
+
+        name_matches
+            && path_matches
+            && rule.vendor.is_none_or(|vendor| vendor == self.vendor)
+            && rule.product.is_none_or(|product| product == self.product)
+    }
+
+    // The same keyboard on the same node, as opposed to another keyboard
+    // that got the node number after it was unplugged
+    pub fn same_device(&self, other: &DeviceInfo) -> bool {
+        self.path == other.path
+            && self.name == other.name
+            && self.vendor == other.vendor
+            && self.product == other.product
+    }
+}
+
+impl std::fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({:04x}:{:04x}, {})",
+            self.name,
+            self.vendor,
+            self.product,
+            self.path.display()
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EvdevEvent {
+    Key(KeyEvent),
+    // The kernel's event buffer overflowed (SYN_DROPPED); releases may have
+    // been lost
+    Dropped,
+    // A keyboard went away; keys held on it will never be released
+    DeviceRemoved,
+}
+
+// Decodes raw `struct input_event` records, as read from an event device or a
+// recording of one. Non-key events are dropped; a trailing partial record is
+// ignored. Keeps state across reads: after SYN_DROPPED the kernel's events are
+// incomplete up to the next SYN_REPORT, so those are skipped.
+#[derive(Default)]
+pub struct KeyDecoder {
+    dropping: bool,
+}
+
+impl KeyDecoder {
+    pub fn decode<'a>(&'a mut self, bytes: &'a [u8]) -> impl Iterator<Item = EvdevEvent> + 'a {
+        bytes
+            .chunks_exact(std::mem::size_of::<libc::input_event>())
+            .filter_map(|record| {
+                let event = unsafe {
+                    std::ptr::read_unaligned(record.as_ptr() as *const libc::input_event)
+                };
+                match (event.type_, event.code, event.value) {
+                    (EV_SYN, SYN_DROPPED, _) => {
+                        self.dropping = true;
+                        Some(EvdevEvent::Dropped)
+                    }
+                    (EV_SYN, SYN_REPORT, _) => {
+                        self.dropping = false;
+                        None
+                    }
+                    _ if self.dropping => None,
+                    (EV_KEY, code, 0) => Some(EvdevEvent::Key(KeyEvent {
+                        code,
+                        pressed: false,
+                        repeat: false,
+                    })),
+                    (EV_KEY, code, 1) => Some(EvdevEvent::Key(KeyEvent {
+                        code,
+                        pressed: true,
+                        repeat: false,
+                    })),
+                    (EV_KEY, code, 2) => Some(EvdevEvent::Key(KeyEvent {
+                        code,
+                        pressed: true,
+                        repeat: true,
+                    })),
+                    _ => None,
+                }
+            })
+    }
+}
+
+struct Device {
+    info: DeviceInfo,
+    file: File,
+    decoder: KeyDecoder,
+}
+
+impl Device {
+    // Returns `None` for devices that can't be opened or aren't keyboards
+    // (mice, power buttons, lid switches, ...)
+    fn open(path: &Path) -> Option<Self> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open(path)
+            .ok()?;
+
+        if !is_keyboard(&file) {
+            return None;
+        }
+
+        let (vendor, product) = device_id(&file).unwrap_or_default();
+        Some(Self {
+            info: DeviceInfo {
+                path: path.to_path_buf(),
+                name: device_name(&file).unwrap_or_default().into(),
+                vendor,
+                product,
+            },
+            file,
+            decoder: KeyDecoder::default(),
+        })
+    }
+
+    // Returns false once the device is gone
+    fn read_events(&mut self, on_event: &mut impl FnMut(&DeviceInfo, EvdevEvent)) -> bool {
+        let mut buffer = [0u8; 64 * std::mem::size_of::<libc::input_event>()];
+        loop {
+            match self.file.read(&mut buffer) {
+                Ok(0) => return false,
+                Ok(len) => {
+                    for event in self.decoder.decode(&buffer[..len]) {
+                        on_event(&self.info, event);
+                    }
+                }
+                Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => return true,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                // ENODEV after unplugging
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+// _IOC(_IOC_READ, 'E', nr, len) from linux/input.h
+fn eviocg(nr: u32, len: usize) -> u32 {
+    (2 << 30) | ((len as u32) << 16) | ((b'E' as u32) << 8) | nr
+}
+
+// Anything that can send letters and space counts as a keyboard
+fn is_keyboard(file: &File) -> bool {
+    let mut bits = [0u8; KEY_MAX / 8 + 1];
+    // EVIOCGBIT(EV_KEY, len)
+    let request = eviocg(0x20 + EV_KEY as u32, bits.len());
+    if unsafe { libc::ioctl(file.as_raw_fd(), request as _, bits.as_mut_ptr()) } < 0 {
+        return false;
+    }
+    let has_key = |code: usize| bits[code / 8] & (1 << (code % 8)) != 0;
+    has_key(KEY_A) && has_key(KEY_SPACE)
+}
+
+fn device_name(file: &File) -> Option<String> {
+    let mut name = [0u8; 256];
+    // EVIOCGNAME(len)
+    let request = eviocg(0x06, name.len());
+    if unsafe { libc::ioctl(file.as_raw_fd(), request as _, name.as_mut_ptr()) } < 0 {
+        return None;
+    }
+    let null_pos = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    Some(String::from_utf8_lossy(&name[..null_pos]).to_string())
+}
+
+// (vendor, product) from EVIOCGID
+fn device_id(file: &File) -> Option<(u16, u16)> {
+    // struct input_id { bustype, vendor, product, version }
+    let mut id = [0u16; 4];
+    let request = eviocg(0x02, std::mem::size_of_val(&id));
+    if unsafe { libc::ioctl(file.as_raw_fd(), request as _, id.as_mut_ptr()) } < 0 {
+        return None;
+    }
+    Some((id[1], id[2]))
+}
+
+// Watches /dev/input so keyboards plugged in later are picked up. Nodes are
+// created by the kernel before udev fixes their permissions, hence IN_ATTRIB.
+fn watch_input_dir() -> std::io::Result<File> {
+    let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let inotify = unsafe { File::from_raw_fd(fd) };
+
+    let dir = std::ffi::CString::new(INPUT_DIR).unwrap();
+    let mask = libc::IN_CREATE | libc::IN_ATTRIB | libc::IN_MOVED_TO;
+    if unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), mask) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(inotify)
+}
+
+pub struct EvdevListener {
+    inotify: File,
+    devices: Vec<Device>,
+    // Empty: read every keyboard
+    rules: Vec<DeviceMatch>,
+    // Keyboards skipped because of `rules`, so they are logged once. Kept by
+    // identity: a node freed by an ignored keyboard may be reused by another.
+    ignored: Vec<DeviceInfo>,
+}
+
+impl EvdevListener {
+    pub fn new(rules: Vec<DeviceMatch>) -> std::io::Result<Self> {
+        let mut listener = Self {
+            inotify: watch_input_dir()?,
+            devices: Vec::new(),
+            rules,
+            ignored: Vec::new(),
+        };
+        listener.scan();
+        Ok(listener)
+    }
+
+    // Open every keyboard that isn't open yet
+    fn scan(&mut self) {
+        let Ok(entries) = std::fs::read_dir(INPUT_DIR) else {
+            return;
+        };
+
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let is_event_node = entry.file_name().to_string_lossy().starts_with("event");
+            if !is_event_node || self.devices.iter().any(|device| device.info.path == path) {
+                continue;
+            }
+            // Ignored keyboards are opened again to compare their identity
+            let Some(device) = Device::open(&path) else {
+                continue;
+            };
+
+            let already_ignored = self
+                .ignored
+                .iter()
+                .any(|info| info.same_device(&device.info));
+            if self.rules.is_empty() || self.rules.iter().any(|rule| device.info.matches(rule)) {
+                log::info!(device:% = device.info; "Keyboard added");
+                self.devices.push(device);
+            } else if !already_ignored {
+                log::info!(device:% = device.info; "Ignoring keyboard not listed in [input] devices");
+                self.ignored.push(device.info);
+            }
+        }
+    }
+
+    // Read events until an unrecoverable error. Starting without any keyboard
+    // is fine: one may be plugged in later.
+    pub fn run(
+        &mut self,
+        mut on_event: impl FnMut(&DeviceInfo, EvdevEvent),
+    ) -> std::io::Result<()> {
+        if self.devices.is_empty() {
+            log::warn!("No matching keyboards in /dev/input yet");
+        }
+
+        loop {
+            let mut pollfds: Vec<libc::pollfd> = std::iter::once(&self.inotify)
+                .chain(self.devices.iter().map(|device| &device.file))
+                .map(|file| libc::pollfd {
+                    fd: file.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                })
+                .collect();
+
+            let ready =
+                unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, -1) };
+            if ready < 0 {
+                let error = std::io::Error::last_os_error();
+                if error.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error);
+            }
+
+            // Device events first: the indices match `self.devices` until we
+            // remove or add any
+            let mut removed = Vec::new();
+            for (index, pollfd) in pollfds[1..].iter().enumerate() {
+                if pollfd.revents == 0 {
+                    continue;
+                }
+                let device = &mut self.devices[index];
+                if pollfd.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0
+                    || !device.read_events(&mut on_event)
+                {
+                    removed.push(index);
+                }
+            }
+            for index in removed.into_iter().rev() {
+                let device = self.devices.remove(index);
+                log::info!(device:% = device.info; "Keyboard removed");
+                on_event(&device.info, EvdevEvent::DeviceRemoved);
+            }
+
+            if pollfds[0].revents != 0 {
+                // Drain the notifications; which file changed doesn't matter
+                let mut buffer = [0u8; 4096];
+                while matches!(self.inotify.read(&mut buffer), Ok(len) if len > 0) {}
+                // Forget ignored keyboards that were unplugged
+                self.ignored.retain(|info| info.path.exists());
+                self.scan();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EV_MSC: u16 = 0x04;
+    const KEY_J: u16 = 36;
+
+    fn record(type_: u16, code: u16, value: i32) -> Vec<u8> {
+        let event = libc::input_event {
+            time: libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            type_,
+            code,
+            value,
+        };
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                &event as *const libc::input_event as *const u8,
+                std::mem::size_of::<libc::input_event>(),
+            )
+        };
+        bytes.to_vec()
+    }
+
+    fn key(code: u16, pressed: bool, repeat: bool) -> EvdevEvent {
+        EvdevEvent::Key(KeyEvent {
+            code,
+            pressed,
+            repeat,
+        })
+    }
+
+    fn device(path: &str, name: &str, vendor: u16, product: u16) -> DeviceInfo {
+        DeviceInfo {
+            path: PathBuf::from(path),
+            name: name.into(),
+            vendor,
+            product,
+        }
+    }
+
+    #[test]
+    fn reused_node_is_another_device() {
+        let ergodox = device("/dev/input/event5", "ZSA ErgoDox EZ", 0x3297, 0x4976);
+        assert!(ergodox.same_device(&ergodox.clone()));
+        let laptop = device("/dev/input/event5", "AT Translated Set 2 keyboard", 1, 1);
+        assert!(!ergodox.same_device(&laptop));
+        let moved = device("/dev/input/event6", "ZSA ErgoDox EZ", 0x3297, 0x4976);
+        assert!(!ergodox.same_device(&moved));
+    }
+
+    #[test]
+    fn decodes_a_recorded_buffer() {
+        let mut bytes = [
+            // Scan code that comes with every key event
+            record(EV_MSC, 4, 0x7000d),
+            record(EV_KEY, KEY_J, 1),
+            record(EV_SYN, SYN_REPORT, 0),
+            record(EV_KEY, KEY_J, 2),
+            record(EV_SYN, SYN_REPORT, 0),
+            record(EV_KEY, KEY_J, 0),
+            record(EV_SYN, SYN_REPORT, 0),
+            // Overflow: what follows up to the next report is incomplete
+            record(EV_SYN, SYN_DROPPED, 0),
+            record(EV_KEY, KEY_A as u16, 0),
+            record(EV_SYN, SYN_REPORT, 0),
+            record(EV_KEY, KEY_SPACE as u16, 1),
+            record(EV_SYN, SYN_REPORT, 0),
+        ]
+        .concat();
+        // A partial record at the end of a read
+        bytes.extend_from_slice(&record(EV_KEY, KEY_J, 1)[..4]);
+
+        let events: Vec<_> = KeyDecoder::default().decode(&bytes).collect();
+        assert_eq!(
+            events,
+            [
+                key(KEY_J, true, false),
+                key(KEY_J, true, true),
+                key(KEY_J, false, false),
+                EvdevEvent::Dropped,
+                key(KEY_SPACE as u16, true, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn skipping_after_dropped_spans_reads() {
+        let mut decoder = KeyDecoder::default();
+        let first = record(EV_SYN, SYN_DROPPED, 0);
+        assert_eq!(
+            decoder.decode(&first).collect::<Vec<_>>(),
+            [EvdevEvent::Dropped]
+        );
+
+        let second = [
+            record(EV_KEY, KEY_J, 0),
+            record(EV_SYN, SYN_REPORT, 0),
+            record(EV_KEY, KEY_J, 1),
+        ]
+        .concat();
+        assert_eq!(
+            decoder.decode(&second).collect::<Vec<_>>(),
+            [key(KEY_J, true, false)]
+        );
+    }
+}
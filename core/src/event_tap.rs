@@ -0,0 +1,196 @@
+// macOS input backend on a Quartz event tap of our own. rdev::listen never
+// removes its tap, so every listener restart after a permission change would
+// leave one more tap behind; this one is torn down when its run loop stops.
+// The tap only listens (kCGEventTapOptionListenOnly), keys still reach the
+// game.
+
+use std::ffi::c_void;
+
+type CFMachPortRef = *mut c_void;
+type CFRunLoopSourceRef = *mut c_void;
+type CFRunLoopRef = *mut c_void;
+type CFStringRef = *const c_void;
+type CGEventRef = *mut c_void;
+type CGEventTapProxy = *mut c_void;
+
+type CGEventTapCallBack = unsafe extern "C" fn(
+    proxy: CGEventTapProxy,
+    event_type: u32,
+    event: CGEventRef,
+    user_info: *mut c_void,
+) -> CGEventRef;
+
+#[link(name = "CoreFoundation", kind = "framework")]
+unsafe extern "C" {
+    static kCFRunLoopCommonModes: CFStringRef;
+
+    fn CFMachPortCreateRunLoopSource(
+        allocator: *const c_void,
+        port: CFMachPortRef,
+        order: isize,
+    ) -> CFRunLoopSourceRef;
+    fn CFMachPortInvalidate(port: CFMachPortRef);
+    fn CFRunLoopGetCurrent() -> CFRunLoopRef;
+    fn CFRunLoopAddSource(run_loop: CFRunLoopRef, source: CFRunLoopSourceRef, mode: CFStringRef);
+    fn CFRunLoopRemoveSource(run_loop: CFRunLoopRef, source: CFRunLoopSourceRef, mode: CFStringRef);
+    fn CFRunLoopRun();
+    fn CFRelease(object: *const c_void);
+}
+
+#[link(name = "CoreGraphics", kind = "framework")]
+unsafe extern "C" {
+    fn CGEventTapCreate(
+        tap: u32,
+        place: u32,
+        options: u32,
+        events_of_interest: u64,
+        callback: CGEventTapCallBack,
+        user_info: *mut c_void,
+    ) -> CFMachPortRef;
+    fn CGEventTapEnable(tap: CFMachPortRef, enable: bool);
+    fn CGEventGetIntegerValueField(event: CGEventRef, field: u32) -> i64;
+    fn CGEventGetFlags(event: CGEventRef) -> u64;
+}
+
+// From CGEventTypes.h
+const HID_EVENT_TAP: u32 = 0;
+const HEAD_INSERT_EVENT_TAP: u32 = 0;
+const TAP_OPTION_LISTEN_ONLY: u32 = 1;
+const EVENT_KEY_DOWN: u32 = 10;
+const EVENT_KEY_UP: u32 = 11;
+const EVENT_FLAGS_CHANGED: u32 = 12;
+const EVENT_TAP_DISABLED_BY_TIMEOUT: u32 = 0xFFFF_FFFE;
+const EVENT_TAP_DISABLED_BY_USER_INPUT: u32 = 0xFFFF_FFFF;
+const KEYBOARD_EVENT_AUTOREPEAT: u32 = 8;
+const KEYBOARD_EVENT_KEYCODE: u32 = 9;
+
+// Device-dependent modifier bits of the event flags (NX_DEVICE*KEYMASK in
+// IOLLEvent.h), per virtual key code. Unlike the shift/control/... flags they
+// tell the left and right keys apart.
+const MODIFIER_MASKS: [(u16, u64); 8] = [
+    (59, 0x0000_0001), // Control left
+    (56, 0x0000_0002), // Shift left
+    (60, 0x0000_0004), // Shift right
+    (55, 0x0000_0008), // Command left
+    (54, 0x0000_0010), // Command right
+    (58, 0x0000_0020), // Option left
+    (61, 0x0000_0040), // Option right
+    (62, 0x0000_2000), // Control right
+];
+const CAPS_LOCK_CODE: u16 = 57;
+const CAPS_LOCK_MASK: u64 = 0x0001_0000;
+
+#[derive(Clone, Copy, Debug)]
+pub struct KeyEvent {
+    // macOS virtual key code (kVK_*)
+    pub code: u16,
+    pub pressed: bool,
+    // System autorepeat of a held key
+    pub repeat: bool,
+}
+
+struct TapContext<'a> {
+    tap: CFMachPortRef,
+    callback: &'a mut dyn FnMut(KeyEvent),
+}
+
+// Runs the current thread's run loop delivering key events to `callback`,
+// until `CFRunLoopStop` ends it. Fails right away when the tap can't be
+// created, which is what a missing permission looks like.
+pub fn listen(mut callback: impl FnMut(KeyEvent)) -> Result<(), String> {
+    let mask = (1 << EVENT_KEY_DOWN) | (1 << EVENT_KEY_UP) | (1 << EVENT_FLAGS_CHANGED);
+    let mut context = TapContext {
+        tap: std::ptr::null_mut(),
+        callback: &mut callback,
+    };
+    // Only used through this pointer while the tap exists
+    let context = &mut context as *mut TapContext;
+
+    unsafe {
+        let tap = CGEventTapCreate(
+            HID_EVENT_TAP,
+            HEAD_INSERT_EVENT_TAP,
+            TAP_OPTION_LISTEN_ONLY,
+            mask,
+            tap_callback,
+            context as *mut c_void,
+        );
+        if tap.is_null() {
+            return Err("failed to create the event tap".to_string());
+        }
+        (*context).tap = tap;
+
+        let source = CFMachPortCreateRunLoopSource(std::ptr::null(), tap, 0);
+        if source.is_null() {
+            CFMachPortInvalidate(tap);
+            CFRelease(tap);
+            return Err("failed to create the event tap's run loop source".to_string());
+        }
+
+        let run_loop = CFRunLoopGetCurrent();
+        CFRunLoopAddSource(run_loop, source, kCFRunLoopCommonModes);
+        CGEventTapEnable(tap, true);
+        CFRunLoopRun();
+
+        // Remove the tap before the next listener creates its own
+        CGEventTapEnable(tap, false);
+        CFRunLoopRemoveSource(run_loop, source, kCFRunLoopCommonModes);
+        CFMachPortInvalidate(tap);
+        CFRelease(source);
+        CFRelease(tap);
+    }
+    Ok(())
+}
+
+unsafe extern "C" fn tap_callback(
+    _proxy: CGEventTapProxy,
+    event_type: u32,
+    event: CGEventRef,
+    user_info: *mut c_void,
+) -> CGEventRef {
+    let context = unsafe { &mut *(user_info as *mut TapContext) };
+
+    let key_event = match event_type {
+        // The system turns slow or interrupted taps off; keep this one going
+        // instead of creating another
+        EVENT_TAP_DISABLED_BY_TIMEOUT | EVENT_TAP_DISABLED_BY_USER_INPUT => {
+            log::warn!(event_type = event_type; "Event tap was disabled, re-enabling it");
+            unsafe { CGEventTapEnable(context.tap, true) };
+            return event;
+        }
+        EVENT_KEY_DOWN | EVENT_KEY_UP => KeyEvent {
+            code: key_code(event),
+            pressed: event_type == EVENT_KEY_DOWN,
+            repeat: unsafe { CGEventGetIntegerValueField(event, KEYBOARD_EVENT_AUTOREPEAT) } != 0,
+        },
+        EVENT_FLAGS_CHANGED => {
+            let code = key_code(event);
+            let flags = unsafe { CGEventGetFlags(event) };
+            let mask = match code {
+                // Caps Lock reports its lock state: it shows as held while on
+                CAPS_LOCK_CODE => CAPS_LOCK_MASK,
+                _ => match MODIFIER_MASKS
+                    .iter()
+                    .find(|&&(modifier, _)| modifier == code)
+                {
+                    Some(&(_, mask)) => mask,
+                    // Fn and other keys without a side bit
+                    None => return event,
+                },
+            };
+            KeyEvent {
+                code,
+                pressed: flags & mask != 0,
+                repeat: false,
+            }
+        }
+        _ => return event,
+    };
+
+    (context.callback)(key_event);
+    event
+}
+
+fn key_code(event: CGEventRef) -> u16 {
+    unsafe { CGEventGetIntegerValueField(event, KEYBOARD_EVENT_KEYCODE) as u16 }
+}
@@ -0,0 +1,373 @@
+// Icons drawn at the bottom of the keys. The built-in set is compiled in; an
+// icon pack (`[icons] pack`) is a directory with an `icons.toml` manifest
+// mapping slot names to image files, loaded over it at startup:
+//
+//     shot = "shot.png"
+//     bomb = "bomb.png"
+//
+// Slots the pack leaves out, or whose image fails to load, keep their
+// built-in icon.
+//
+// Bitmaps (PNG, ...) are scaled when drawn. SVG icons are rasterized with
+// resvg at each size they are drawn at, in physical pixels, and again when the
+// window's DPI scale changes, so they stay sharp on HiDPI screens. Their
+// `currentColor` is white, so the tint gives them their color.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use raylib::prelude::*;
+use resvg::{tiny_skia, usvg};
+
+use crate::bindings::Slot;
+use crate::config::IconConfig;
+
+const MANIFEST_FILE: &str = "icons.toml";
+
+// Material Symbols as published on Iconify, drawn with `currentColor`
+const ARROW_LEFT_SVG: &[u8] = include_bytes!("../../icons/MaterialSymbolsArrowBack.svg");
+const ARROW_RIGHT_SVG: &[u8] = include_bytes!("../../icons/MaterialSymbolsArrowForward.svg");
+const ARROW_UP_SVG: &[u8] = include_bytes!("../../icons/MaterialSymbolsArrowUpward.svg");
+const ARROW_DOWN_SVG: &[u8] = include_bytes!("../../icons/MaterialSymbolsArrowDownward.svg");
+const FOCUS_SVG: &[u8] = include_bytes!("../../icons/MaterialSymbolsFilterCenterFocus.svg");
+const REFRESH_SVG: &[u8] = include_bytes!("../../icons/MaterialSymbolsRefresh.svg");
+const BOMB_SVG: &[u8] = include_bytes!("../../icons/MaterialSymbolsBomb.svg");
+// Stands in for the EOS Icons troubleshooting glyph, same 24px grid
+const SHOOT_SVG: &[u8] = include_bytes!("../../icons/EosIconsTroubleshooting.svg");
+
+// Name (for errors), file type, image and the slots that show it
+const BUILTIN_ICONS: [(&str, &str, &[u8], &[Slot]); 8] = [
+    ("arrow_left", ".svg", ARROW_LEFT_SVG, &[Slot::Left]),
+    ("arrow_right", ".svg", ARROW_RIGHT_SVG, &[Slot::Right]),
+    ("arrow_up", ".svg", ARROW_UP_SVG, &[Slot::Up]),
+    ("arrow_down", ".svg", ARROW_DOWN_SVG, &[Slot::Down]),
+    ("bomb", ".svg", BOMB_SVG, &[Slot::Bomb]),
+    // Shift is focus in the stock Touhou controls
+    (
+        "focus",
+        ".svg",
+        FOCUS_SVG,
+        &[Slot::Focus, Slot::ShiftLeft, Slot::ShiftRight],
+    ),
+    ("refresh", ".svg", REFRESH_SVG, &[Slot::Retry]),
+    ("shoot", ".svg", SHOOT_SVG, &[Slot::Shot]),
+];
+
+// `currentColor` is white, so the icon tint (`[theme.icon]`) sets the color
+const SVG_STYLE: &str = "svg { color: white }";
+
+enum IconImage {
+    Bitmap(Texture2D),
+    Vector {
+        tree: Box<usvg::Tree>,
+        // Rasterized per size, keyed by the size in physical pixels
+        textures: Vec<(u32, Texture2D)>,
+    },
+}
+
+pub struct GameIcons {
+    images: Vec<IconImage>,
+    // Index into `images` per slot
+    slots: [Option<usize>; Slot::COUNT],
+    // What the vector icons are currently rasterized for
+    scale: f32,
+    sizes: Vec<f32>,
+}
+
+impl GameIcons {
+    pub fn new() -> Self {
+        Self {
+            images: Vec::new(),
+            slots: [None; Slot::COUNT],
+            scale: 0.0,
+            sizes: Vec::new(),
+        }
+    }
+
+    // Built-in icons, then the pack over them. Returns what failed to load
+    // (icon or slot names, "manifest"); details are logged.
+    pub fn load_icons(
+        &mut self,
+        rl: &mut RaylibHandle,
+        thread: &RaylibThread,
+        config: &IconConfig,
+    ) -> Vec<String> {
+        log::debug!("Loading embedded icons");
+
+        let mut failed = Vec::new();
+        for (name, file_type, bytes, slots) in BUILTIN_ICONS {
+            match load_image(rl, thread, file_type, bytes) {
+                Ok(image) => self.add(image, slots),
+                Err(error) => {
+                    log::warn!(icon = name, error:% = error; "Failed to load icon");
+                    failed.push(name.to_string());
+                }
+            }
+        }
+
+        if let Some(pack) = &config.pack {
+            self.load_pack(rl, thread, pack, &mut failed);
+        }
+
+        failed
+    }
+
+    fn load_pack(
+        &mut self,
+        rl: &mut RaylibHandle,
+        thread: &RaylibThread,
+        pack: &Path,
+        failed: &mut Vec<String>,
+    ) {
+        let manifest_path = pack.join(MANIFEST_FILE);
+        let manifest = std::fs::read_to_string(&manifest_path)
+            .map_err(|error| error.to_string())
+            .and_then(|text| {
+                toml::from_str::<BTreeMap<String, String>>(&text).map_err(|error| error.to_string())
+            });
+        let manifest = match manifest {
+            Ok(manifest) => manifest,
+            Err(error) => {
+                log::error!(path:% = manifest_path.display(), error:% = error; "Failed to read icon pack manifest");
+                failed.push("manifest".to_string());
+                return;
+            }
+        };
+
+        let mut loaded = 0;
+        for (slot_name, file) in &manifest {
+            let Some(slot) = Slot::from_name(slot_name) else {
+                log::error!(path:% = manifest_path.display(), slot = slot_name.as_str(); "Unknown slot in icon pack manifest");
+                failed.push(slot_name.clone());
+                continue;
+            };
+
+            let path = pack.join(file);
+            match load_file(rl, thread, &path) {
+                Ok(image) => {
+                    self.add(image, &[slot]);
+                    loaded += 1;
+                }
+                Err(error) => {
+                    log::error!(slot = slot_name.as_str(), path:% = path.display(), error:% = error; "Failed to load icon from pack, keeping the built-in one");
+                    failed.push(slot_name.clone());
+                }
+            }
+        }
+        log::info!(path:% = pack.display(), icons = loaded; "Loaded icon pack");
+    }
+
+    fn add(&mut self, image: IconImage, slots: &[Slot]) {
+        self.images.push(image);
+        for &slot in slots {
+            self.slots[slot as usize] = Some(self.images.len() - 1);
+        }
+    }
+
+    // Rasterize the vector icons for the sizes they are drawn at (in window
+    // coordinates) and the window's DPI scale. Does nothing unless either
+    // changed, so it can run every frame.
+    pub fn rasterize(
+        &mut self,
+        rl: &mut RaylibHandle,
+        thread: &RaylibThread,
+        scale: f32,
+        sizes: &[f32],
+    ) {
+        if scale == self.scale && sizes == self.sizes.as_slice() {
+            return;
+        }
+        self.scale = scale;
+        self.sizes = sizes.to_vec();
+
+        let mut count = 0;
+        for image in &mut self.images {
+            let IconImage::Vector { tree, textures } = image else {
+                continue;
+            };
+            textures.clear();
+            for &size in sizes {
+                let pixels = pixel_size(size, scale);
+                if textures.iter().any(|&(done, _)| done == pixels) {
+                    continue;
+                }
+                match rasterize_svg(rl, thread, tree, pixels) {
+                    Ok(texture) => textures.push((pixels, texture)),
+                    Err(error) => {
+                        log::warn!(pixels = pixels, error:% = error; "Failed to rasterize icon")
+                    }
+                }
+            }
+            count += 1;
+        }
+        if count > 0 {
+            log::debug!(icons = count, scale = scale, sizes:? = sizes; "Rasterized vector icons");
+        }
+    }
+
+    // The texture to draw at `size` (in window coordinates); scale it by
+    // `size / width` when drawing
+    pub fn get_icon_texture(&self, slot: Slot, size: f32) -> Option<&Texture2D> {
+        let index = self.slots[slot as usize]?;
+        match &self.images[index] {
+            IconImage::Bitmap(texture) => Some(texture),
+            IconImage::Vector { textures, .. } => {
+                let pixels = pixel_size(size, self.scale);
+                textures
+                    .iter()
+                    .find(|&&(done, _)| done == pixels)
+                    .or_else(|| textures.first())
+                    .map(|(_, texture)| texture)
+            }
+        }
+    }
+}
+
+fn pixel_size(size: f32, scale: f32) -> u32 {
+    (size * scale).round().max(1.0) as u32
+}
+
+// The format comes from the file extension, e.g. "shot.png" or "shot.svg"
+fn load_file(
+    rl: &mut RaylibHandle,
+    thread: &RaylibThread,
+    path: &Path,
+) -> Result<IconImage, String> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .ok_or_else(|| "no file extension".to_string())?;
+    let bytes = std::fs::read(path).map_err(|error| error.to_string())?;
+    load_image(
+        rl,
+        thread,
+        &format!(".{}", extension.to_lowercase()),
+        &bytes,
+    )
+}
+
+// SVGs are only parsed here; `GameIcons::rasterize` makes their textures
+fn load_image(
+    rl: &mut RaylibHandle,
+    thread: &RaylibThread,
+    file_type: &str,
+    bytes: &[u8],
+) -> Result<IconImage, String> {
+    if file_type == ".svg" {
+        let tree =
+            usvg::Tree::from_data(bytes, &svg_options()).map_err(|error| error.to_string())?;
+        return Ok(IconImage::Vector {
+            tree: Box::new(tree),
+            textures: Vec::new(),
+        });
+    }
+
+    Image::load_image_from_mem(file_type, bytes)
+        .and_then(|image| rl.load_texture_from_image(thread, &image))
+        .map(IconImage::Bitmap)
+        .map_err(|error| error.to_string())
+}
+
+fn svg_options() -> usvg::Options<'static> {
+    usvg::Options {
+        style_sheet: Some(SVG_STYLE.to_string()),
+        ..usvg::Options::default()
+    }
+}
+
+fn rasterize_svg(
+    rl: &mut RaylibHandle,
+    thread: &RaylibThread,
+    tree: &usvg::Tree,
+    pixels: u32,
+) -> Result<Texture2D, String> {
+    let pixmap = render_svg(tree, pixels)?;
+
+    // tiny-skia premultiplies alpha, raylib textures don't
+    let rgba: Vec<u8> = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+    let image = Image::gen_image_color(pixels as i32, pixels as i32, Color::BLANK);
+    let mut texture = rl
+        .load_texture_from_image(thread, &image)
+        .map_err(|error| error.to_string())?;
+    texture
+        .update_texture(&rgba)
+        .map_err(|error| error.to_string())?;
+    Ok(texture)
+}
+
+// Fit into a square of `pixels`, centered
+fn render_svg(tree: &usvg::Tree, pixels: u32) -> Result<tiny_skia::Pixmap, String> {
+    let mut pixmap =
+        tiny_skia::Pixmap::new(pixels, pixels).ok_or_else(|| format!("invalid size {}", pixels))?;
+    let size = tree.size();
+    let fit = pixels as f32 / size.width().max(size.height());
+    let transform = tiny_skia::Transform::from_scale(fit, fit).post_translate(
+        (pixels as f32 - size.width() * fit) / 2.0,
+        (pixels as f32 - size.height() * fit) / 2.0,
+    );
+    resvg::render(tree, transform, &mut pixmap.as_mut());
+    Ok(pixmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_svgs_parse_in_white() {
+        for (name, file_type, bytes, _) in BUILTIN_ICONS {
+            assert_eq!(file_type, ".svg", "{name}");
+            let tree = usvg::Tree::from_data(bytes, &svg_options()).unwrap();
+            let size = tree.size();
+            assert_eq!((size.width(), size.height()), (24.0, 24.0), "{name}");
+
+            let usvg::Node::Path(path) = &tree.root().children()[0] else {
+                panic!("{name} doesn't start with a path");
+            };
+            let paint = path.fill().map(|fill| fill.paint());
+            assert!(
+                matches!(paint, Some(usvg::Paint::Color(color)) if *color == usvg::Color::white()),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn pixel_size_rounds_to_physical_pixels() {
+        assert_eq!(pixel_size(24.0, 1.0), 24);
+        assert_eq!(pixel_size(24.0, 2.0), 48);
+        assert_eq!(pixel_size(21.0, 1.25), 26);
+        assert_eq!(pixel_size(21.4, 1.0), 21);
+        // Never an empty texture
+        assert_eq!(pixel_size(0.2, 1.0), 1);
+    }
+
+    #[test]
+    fn builtin_svgs_rasterize_white_at_each_size() {
+        for (name, _, bytes, _) in BUILTIN_ICONS {
+            let tree = usvg::Tree::from_data(bytes, &svg_options()).unwrap();
+            for pixels in [pixel_size(24.0, 1.0), pixel_size(24.0, 1.5)] {
+                let pixmap = render_svg(&tree, pixels).unwrap();
+                assert_eq!(
+                    (pixmap.width(), pixmap.height()),
+                    (pixels, pixels),
+                    "{name}"
+                );
+
+                let white = pixmap.pixels().iter().any(|pixel| {
+                    let color = pixel.demultiply();
+                    (color.red(), color.green(), color.blue(), color.alpha())
+                        == (255, 255, 255, 255)
+                });
+                assert!(white, "{name} at {pixels}px has no opaque white pixel");
+            }
+        }
+    }
+}
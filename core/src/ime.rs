@@ -0,0 +1,90 @@
+// Input methods on Linux. Fcitx 5 and IBus sit above the xkb keymap, so the
+// keymap can't tell when Japanese or Chinese input is on; their command line
+// tools (`fcitx5-remote -n`, `ibus engine`) name the active engine instead.
+
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Framework {
+    Fcitx5,
+    IBus,
+}
+
+impl Framework {
+    const ALL: [Framework; 2] = [Framework::Fcitx5, Framework::IBus];
+
+    fn command(self) -> Command {
+        let (program, arg) = match self {
+            Framework::Fcitx5 => ("fcitx5-remote", "-n"),
+            Framework::IBus => ("ibus", "engine"),
+        };
+        let mut command = Command::new(program);
+        command.arg(arg).stdin(Stdio::null()).stderr(Stdio::null());
+        command
+    }
+
+    // e.g. "mozc" or "keyboard-us"; `Ok(None)` when the framework isn't
+    // running, `Err` when its tool isn't installed
+    fn active_engine(self) -> std::io::Result<Option<String>> {
+        let output = self.command().output()?;
+        let engine = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok((output.status.success() && !engine.is_empty()).then_some(engine))
+    }
+
+    // Whether `engine` composes text instead of typing through a keyboard
+    // layout
+    fn is_input_method(self, engine: &str) -> bool {
+        match self {
+            // Layouts are "keyboard-<layout>[-<variant>]"
+            Framework::Fcitx5 => !engine.starts_with("keyboard-"),
+            // Layouts are "xkb:<layout>:<variant>:<language>"
+            Framework::IBus => !engine.starts_with("xkb:"),
+        }
+    }
+}
+
+pub struct InputMethodWatch {
+    // The ones whose tool is installed, asked in this order
+    frameworks: Vec<Framework>,
+}
+
+impl InputMethodWatch {
+    // `None` when neither tool is installed
+    pub fn new() -> Option<Self> {
+        let frameworks: Vec<_> = Framework::ALL
+            .into_iter()
+            .filter(|framework| framework.active_engine().is_ok())
+            .collect();
+        (!frameworks.is_empty()).then_some(Self { frameworks })
+    }
+
+    // The engine composing text, if any. The first framework that runs
+    // decides, since only one of them gets the keys.
+    pub fn poll(&self) -> Option<String> {
+        self.frameworks
+            .iter()
+            .find_map(|&framework| {
+                let engine = framework.active_engine().ok()??;
+                Some(framework.is_input_method(&engine).then_some(engine))
+            })
+            .flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyboard_engines_are_not_input_methods() {
+        assert!(!Framework::Fcitx5.is_input_method("keyboard-us"));
+        assert!(!Framework::Fcitx5.is_input_method("keyboard-de-neo"));
+        assert!(Framework::Fcitx5.is_input_method("mozc"));
+        assert!(Framework::Fcitx5.is_input_method("pinyin"));
+
+        assert!(!Framework::IBus.is_input_method("xkb:us::eng"));
+        assert!(!Framework::IBus.is_input_method("xkb:us:colemak:eng"));
+        assert!(Framework::IBus.is_input_method("mozc-jp"));
+        assert!(Framework::IBus.is_input_method("libpinyin"));
+    }
+}
@@ -0,0 +1,551 @@
+// Where key events come from. The monitor process drives one `InputSource`
+// and applies whatever it reports to shared memory; scripted and replayed
+// sources make that path usable without a real keyboard hook.
+
+use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::rc::Rc;
+#[cfg(target_os = "macos")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::keys::PhysicalKey;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum InputEvent {
+    // `repeat` marks an autorepeated press of a key that is already held
+    Key {
+        key: PhysicalKey,
+        pressed: bool,
+        repeat: bool,
+        raw: RawKey,
+    },
+    // A key with no `PhysicalKey`, e.g. on the numpad. Only the key inspector
+    // shows these.
+    Unmapped {
+        raw: RawKey,
+        pressed: bool,
+    },
+    // Releases may have been missed, e.g. the keyboard was unplugged
+    ReleaseAll,
+    // Switch to the layout with this name. Only scripts send it, to show the
+    // layout toast and history without a layout provider.
+    Layout {
+        name: String,
+    },
+}
+
+// The key as the backend reported it, for the key inspector
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RawKey {
+    #[cfg(not(target_os = "macos"))]
+    Rdev(rdev::Key),
+    // Linux key code (KEY_*) and the name of the keyboard
+    #[cfg(target_os = "linux")]
+    Evdev {
+        code: u16,
+        device: Rc<str>,
+    },
+    // macOS virtual key code (kVK_*)
+    #[cfg(target_os = "macos")]
+    EventTap(u16),
+    Script,
+}
+
+impl std::fmt::Display for RawKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            #[cfg(not(target_os = "macos"))]
+            RawKey::Rdev(key) => write!(f, "rdev {:?}", key),
+            #[cfg(target_os = "linux")]
+            RawKey::Evdev { code, device } => write!(f, "evdev code {} on {}", code, device),
+            #[cfg(target_os = "macos")]
+            RawKey::EventTap(code) => write!(f, "event tap code {}", code),
+            RawKey::Script => f.write_str("script"),
+        }
+    }
+}
+
+// Receives the events of a source. Boxed and 'static because rdev hands its
+// callback to the OS event loop.
+pub type InputSink = Box<dyn FnMut(InputEvent)>;
+
+pub trait InputSource {
+    // Blocks delivering events to `sink`. `Ok(())` means the source ended on
+    // its own; both outcomes make the caller restart it after a backoff.
+    fn run(&mut self, sink: InputSink) -> Result<(), String>;
+}
+
+// Tags presses of keys that are already down as autorepeat, for sources that
+// don't report repeats themselves
+#[cfg(not(target_os = "macos"))]
+#[derive(Default)]
+pub struct RepeatTracker {
+    held: Vec<PhysicalKey>,
+}
+
+#[cfg(not(target_os = "macos"))]
+impl RepeatTracker {
+    // Returns the event for a raw press/release
+    pub fn event(&mut self, key: PhysicalKey, pressed: bool, raw: RawKey) -> InputEvent {
+        let was_held = self.held.contains(&key);
+        if pressed && !was_held {
+            self.held.push(key);
+        } else if !pressed {
+            self.held.retain(|&held| held != key);
+        }
+
+        InputEvent::Key {
+            key,
+            pressed,
+            repeat: pressed && was_held,
+            raw,
+        }
+    }
+}
+
+// Set while the event tap is running on the main thread
+#[cfg(target_os = "macos")]
+pub static LISTENER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+// Global hook through rdev, on X11 and Windows. macOS uses `EventTapSource`,
+// which removes its tap when it stops.
+#[cfg(not(target_os = "macos"))]
+pub struct RdevSource;
+
+#[cfg(not(target_os = "macos"))]
+impl InputSource for RdevSource {
+    fn run(&mut self, mut sink: InputSink) -> Result<(), String> {
+        // rdev reports autorepeat as plain KeyPress events
+        let mut repeats = RepeatTracker::default();
+
+        rdev::listen(move |event| {
+            let (rdev_key, pressed) = match event.event_type {
+                rdev::EventType::KeyPress(key) => (key, true),
+                rdev::EventType::KeyRelease(key) => (key, false),
+                _ => return, // Ignore other events
+            };
+            let raw = RawKey::Rdev(rdev_key);
+            match PhysicalKey::from_rdev(rdev_key) {
+                Some(key) => sink(repeats.event(key, pressed, raw)),
+                None => sink(InputEvent::Unmapped { raw, pressed }),
+            }
+        })
+        .map_err(|error| format!("{:?}", error))
+    }
+}
+
+// Global hook through a Quartz event tap
+#[cfg(target_os = "macos")]
+pub struct EventTapSource;
+
+#[cfg(target_os = "macos")]
+impl InputSource for EventTapSource {
+    fn run(&mut self, mut sink: InputSink) -> Result<(), String> {
+        LISTENER_RUNNING.store(true, Ordering::SeqCst);
+        let result = crate::event_tap::listen(|event| {
+            let raw = RawKey::EventTap(event.code);
+            match PhysicalKey::from_mac_vk(event.code) {
+                Some(key) => sink(InputEvent::Key {
+                    key,
+                    pressed: event.pressed,
+                    repeat: event.repeat,
+                    raw,
+                }),
+                // Repeats of unmapped keys tell the inspector nothing new
+                None if event.repeat => {}
+                None => sink(InputEvent::Unmapped {
+                    raw,
+                    pressed: event.pressed,
+                }),
+            }
+        });
+        LISTENER_RUNNING.store(false, Ordering::SeqCst);
+
+        result
+    }
+}
+
+// Reads keyboards from /dev/input, picking up hotplugged ones
+#[cfg(target_os = "linux")]
+pub struct EvdevSource {
+    pub devices: Vec<crate::config::DeviceMatch>,
+}
+
+#[cfg(target_os = "linux")]
+impl InputSource for EvdevSource {
+    fn run(&mut self, mut sink: InputSink) -> Result<(), String> {
+        use crate::evdev::{EvdevEvent, EvdevListener};
+
+        let mut listener =
+            EvdevListener::new(self.devices.clone()).map_err(|error| error.to_string())?;
+        listener
+            .run(|device, event| match event {
+                EvdevEvent::Key(key) => {
+                    log::trace!(device:% = device.name, code = key.code, pressed = key.pressed; "Key event");
+                    let raw = RawKey::Evdev {
+                        code: key.code,
+                        device: device.name.clone(),
+                    };
+                    match PhysicalKey::from_evdev(key.code) {
+                        Some(physical_key) => sink(InputEvent::Key {
+                            key: physical_key,
+                            pressed: key.pressed,
+                            repeat: key.repeat,
+                            raw,
+                        }),
+                        // Repeats of unmapped keys tell the inspector nothing new
+                        None if key.repeat => {}
+                        None => sink(InputEvent::Unmapped {
+                            raw,
+                            pressed: key.pressed,
+                        }),
+                    }
+                }
+                // Keys held on an unplugged keyboard never get their release,
+                // and a buffer overflow may have lost some
+                EvdevEvent::DeviceRemoved | EvdevEvent::Dropped => {
+                    sink(InputEvent::ReleaseAll)
+                }
+            })
+            .map_err(|error| error.to_string())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ScriptStep {
+    // Offset from the start of the script
+    pub at: Duration,
+    pub event: InputEvent,
+}
+
+// Plays a fixed list of events in real time, e.g. "press K at 10ms, release
+// it at 30ms"
+pub struct ScriptedSource {
+    steps: Vec<ScriptStep>,
+    // Start over after the last step instead of ending
+    looping: bool,
+    // Wait for each step's time; off, steps follow each other right away
+    realtime: bool,
+}
+
+impl ScriptedSource {
+    pub fn new() -> Self {
+        Self {
+            steps: Vec::new(),
+            looping: false,
+            realtime: true,
+        }
+    }
+
+    pub fn press(mut self, at_ms: u64, key: PhysicalKey) -> Self {
+        self.push(
+            at_ms,
+            InputEvent::Key {
+                key,
+                pressed: true,
+                repeat: false,
+                raw: RawKey::Script,
+            },
+        );
+        self
+    }
+
+    // An autorepeat of a key pressed earlier in the script
+    pub fn repeat(mut self, at_ms: u64, key: PhysicalKey) -> Self {
+        self.push(
+            at_ms,
+            InputEvent::Key {
+                key,
+                pressed: true,
+                repeat: true,
+                raw: RawKey::Script,
+            },
+        );
+        self
+    }
+
+    pub fn release(mut self, at_ms: u64, key: PhysicalKey) -> Self {
+        self.push(
+            at_ms,
+            InputEvent::Key {
+                key,
+                pressed: false,
+                repeat: false,
+                raw: RawKey::Script,
+            },
+        );
+        self
+    }
+
+    pub fn layout(mut self, at_ms: u64, name: &str) -> Self {
+        self.push(
+            at_ms,
+            InputEvent::Layout {
+                name: name.to_string(),
+            },
+        );
+        self
+    }
+
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    // Only tests play scripts faster than written
+    #[cfg(test)]
+    pub fn realtime(mut self, realtime: bool) -> Self {
+        self.realtime = realtime;
+        self
+    }
+
+    fn push(&mut self, at_ms: u64, event: InputEvent) {
+        self.steps.push(ScriptStep {
+            at: Duration::from_millis(at_ms),
+            event,
+        });
+        // Keep steps in time order no matter how the script was written
+        self.steps.sort_by_key(|step| step.at);
+    }
+
+    // One step per line, `<time>ms press|repeat|release <key>` or
+    // `<time>ms layout <name>`; `#` starts a comment. Keys use `PhysicalKey`
+    // names (`a`, `;`, `space`, `esc`, ...).
+    //
+    //     10ms press k
+    //     30ms release k
+    //     50ms layout English (Colemak)
+    pub fn parse(script: &str) -> Result<Self, String> {
+        let mut source = Self::new();
+
+        for (index, line) in script.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {}", index + 1, message);
+
+            let (time, rest) = split_word(line);
+            let (action, argument) = split_word(rest);
+            if action.is_empty() || argument.is_empty() {
+                return Err(error(
+                    "expected `<time>ms press|repeat|release <key>` or `<time>ms layout <name>`",
+                ));
+            }
+
+            let at_ms = time
+                .strip_suffix("ms")
+                .and_then(|ms| ms.parse().ok())
+                .ok_or_else(|| error("time must look like `10ms`"))?;
+            if action == "layout" {
+                source = source.layout(at_ms, argument);
+                continue;
+            }
+            let key = PhysicalKey::from_name(argument).ok_or_else(|| error("unknown key"))?;
+            source = match action {
+                "press" => source.press(at_ms, key),
+                "repeat" => source.repeat(at_ms, key),
+                "release" => source.release(at_ms, key),
+                _ => {
+                    return Err(error(
+                        "action must be `press`, `repeat`, `release` or `layout`",
+                    ));
+                }
+            };
+        }
+
+        Ok(source)
+    }
+}
+
+// The first word of `text` and the trimmed rest
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(end) => (&text[..end], text[end..].trim()),
+        None => (text, ""),
+    }
+}
+
+impl InputSource for ScriptedSource {
+    fn run(&mut self, mut sink: InputSink) -> Result<(), String> {
+        loop {
+            let start = Instant::now();
+            for step in &self.steps {
+                let due = start + step.at;
+                let now = Instant::now();
+                if self.realtime && due > now {
+                    std::thread::sleep(due - now);
+                }
+                sink(step.event.clone());
+            }
+
+            // A looping script without steps would spin
+            if !self.looping || self.steps.is_empty() {
+                return Ok(());
+            }
+            // Don't leave keys held across iterations
+            sink(InputEvent::ReleaseAll);
+        }
+    }
+}
+
+// A script file (see `ScriptedSource::parse`) played in a loop, for demos and
+// checking the UI without a keyboard hook
+pub struct ReplaySource {
+    script: ScriptedSource,
+}
+
+impl ReplaySource {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("{}: {}", path.display(), error))?;
+        let script = ScriptedSource::parse(&text)
+            .map_err(|error| format!("{}: {}", path.display(), error))?;
+        Ok(Self {
+            script: script.looping(true),
+        })
+    }
+}
+
+impl InputSource for ReplaySource {
+    fn run(&mut self, sink: InputSink) -> Result<(), String> {
+        self.script.run(sink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    // Runs `source` without delays and returns what it delivered
+    fn collect(source: ScriptedSource) -> Vec<InputEvent> {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink_events = events.clone();
+        source
+            .realtime(false)
+            .run(Box::new(move |event| sink_events.borrow_mut().push(event)))
+            .unwrap();
+        events.take()
+    }
+
+    fn key(key: PhysicalKey, pressed: bool, repeat: bool) -> InputEvent {
+        InputEvent::Key {
+            key,
+            pressed,
+            repeat,
+            raw: RawKey::Script,
+        }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn tracker_tags_presses_of_held_keys_as_repeats() {
+        let mut repeats = RepeatTracker::default();
+        let mut event = |key, pressed| repeats.event(key, pressed, RawKey::Script);
+
+        assert_eq!(
+            event(PhysicalKey::J, true),
+            key(PhysicalKey::J, true, false)
+        );
+        assert_eq!(event(PhysicalKey::J, true), key(PhysicalKey::J, true, true));
+        assert_eq!(event(PhysicalKey::J, true), key(PhysicalKey::J, true, true));
+        // Other keys are tracked on their own
+        assert_eq!(
+            event(PhysicalKey::K, true),
+            key(PhysicalKey::K, true, false)
+        );
+        assert_eq!(
+            event(PhysicalKey::J, false),
+            key(PhysicalKey::J, false, false)
+        );
+        // Pressed again after the release
+        assert_eq!(
+            event(PhysicalKey::J, true),
+            key(PhysicalKey::J, true, false)
+        );
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn tracker_ignores_releases_of_keys_it_never_saw() {
+        let mut repeats = RepeatTracker::default();
+        assert_eq!(
+            repeats.event(PhysicalKey::J, false, RawKey::Script),
+            key(PhysicalKey::J, false, false)
+        );
+        assert_eq!(
+            repeats.event(PhysicalKey::J, true, RawKey::Script),
+            key(PhysicalKey::J, true, false)
+        );
+    }
+
+    #[test]
+    fn script_plays_in_time_order() {
+        let script = ScriptedSource::parse(
+            "# hold j, tap k while it is held
+             0ms press j
+             40ms release j # comment after a step
+             10ms press k
+             20ms release k
+             30ms repeat j",
+        )
+        .unwrap();
+
+        assert_eq!(
+            collect(script),
+            [
+                key(PhysicalKey::J, true, false),
+                key(PhysicalKey::K, true, false),
+                key(PhysicalKey::K, false, false),
+                key(PhysicalKey::J, true, true),
+                key(PhysicalKey::J, false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn script_errors_name_the_line() {
+        let error = |script| ScriptedSource::parse(script).err().unwrap();
+        assert_eq!(
+            error("0ms press j\n10 release j"),
+            "line 2: time must look like `10ms`"
+        );
+        assert_eq!(error("0ms press nope"), "line 1: unknown key");
+        assert_eq!(
+            error("0ms tap j"),
+            "line 1: action must be `press`, `repeat`, `release` or `layout`"
+        );
+        assert_eq!(
+            error("0ms press"),
+            "line 1: expected `<time>ms press|repeat|release <key>` or `<time>ms layout <name>`"
+        );
+        assert_eq!(error("0ms press j k"), "line 1: unknown key");
+    }
+
+    #[test]
+    fn script_switches_layouts_by_name() {
+        let script = ScriptedSource::parse(
+            "0ms layout U.S.
+             10ms press j
+             20ms layout   English (Colemak)  # spaces around the name are dropped",
+        )
+        .unwrap();
+
+        assert_eq!(
+            collect(script),
+            [
+                InputEvent::Layout {
+                    name: "U.S.".to_string()
+                },
+                key(PhysicalKey::J, true, false),
+                InputEvent::Layout {
+                    name: "English (Colemak)".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,311 @@
+// Key inspector and learn mode, both driven by `SharedState::last_key_press`.
+// The inspector shows each press as the input backend reported it; learn mode
+// asks for a key per slot and writes the result to the config file.
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::SharedState;
+use crate::bindings::{Bindings, Slot};
+use crate::keys::PhysicalKey;
+
+// How long the result of a learn run stays on screen
+const MESSAGE_DURATION: Duration = Duration::from_secs(5);
+
+pub struct KeyInspector {
+    pub visible: bool,
+    // Index into `Slot::LEARN_ORDER` of the slot waiting for a key
+    learning: Option<usize>,
+    // Bindings being learned; only applied once every slot has a key, so
+    // cancelling leaves the old ones in place
+    pending: Bindings,
+    last_seq: u64,
+    message: Option<(String, Instant)>,
+    // Where learned bindings are saved: the selected profile's table, if any
+    profile: Option<String>,
+    config_path: Option<PathBuf>,
+}
+
+impl KeyInspector {
+    pub fn new(state: &SharedState, profile: Option<String>) -> Self {
+        Self {
+            visible: false,
+            learning: None,
+            pending: state.bindings,
+            // Presses from before this UI process started don't count
+            last_seq: state.last_key_press().seq,
+            message: None,
+            profile,
+            config_path: crate::config::config_path(),
+        }
+    }
+
+    pub fn learning_slot(&self) -> Option<Slot> {
+        self.learning.map(|index| Slot::LEARN_ORDER[index])
+    }
+
+    pub fn toggle_learning(&mut self, state: &SharedState) {
+        if self.learning.is_some() {
+            self.cancel_learning();
+        } else {
+            log::info!("Learn mode started");
+            self.pending = state.bindings;
+            self.learning = Some(0);
+            self.message = None;
+        }
+    }
+
+    fn cancel_learning(&mut self) {
+        log::info!("Learn mode cancelled");
+        self.learning = None;
+    }
+
+    // Call once per frame
+    pub fn update(&mut self, shared_state: *mut SharedState) {
+        let state = unsafe { &mut *shared_state };
+        let press = state.last_key_press();
+        if press.seq == self.last_seq {
+            return;
+        }
+        self.last_seq = press.seq;
+
+        let Some(index) = self.learning else {
+            return;
+        };
+        let slot = Slot::LEARN_ORDER[index];
+        let Some(key) = press.key else {
+            log::info!(raw:% = press.raw(); "Learn mode ignored a key that can't be bound");
+            return;
+        };
+        // Escape is the usual pause key, so it only cancels the other slots
+        if key == PhysicalKey::Escape && slot != Slot::Pause {
+            self.cancel_learning();
+            return;
+        }
+        log::info!(slot = slot.name(), key:% = key; "Learned key binding");
+        self.pending.bind(slot, key);
+
+        if index + 1 < Slot::LEARN_ORDER.len() {
+            self.learning = Some(index + 1);
+            return;
+        }
+
+        self.learning = None;
+        state.bindings = self.pending;
+        let message = match self.save() {
+            Ok(path) => {
+                log::info!(path:% = path.display(), profile:? = self.profile; "Saved key bindings");
+                match &self.profile {
+                    Some(profile) => format!(
+                        "Saved bindings for profile {} to {}",
+                        profile,
+                        path.display()
+                    ),
+                    None => format!("Saved bindings to {}", path.display()),
+                }
+            }
+            Err(error) => {
+                log::error!(error:% = error; "Failed to save key bindings");
+                format!("Bindings applied but not saved: {}", error)
+            }
+        };
+        self.message = Some((message, Instant::now()));
+    }
+
+    fn save(&self) -> std::io::Result<PathBuf> {
+        let path = self.config_path.clone().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no config location (HOME is not set)",
+            )
+        })?;
+        crate::config::save_bindings(&path, self.profile.as_deref(), &self.pending.to_toml())?;
+        Ok(path)
+    }
+
+    // Text for the line under the keyboard, if any
+    pub fn status_line(&self, state: &SharedState) -> Option<String> {
+        if let Some(index) = self.learning {
+            let slot = Slot::LEARN_ORDER[index];
+            return Some(format!(
+                "Press the key for {} ({}/{})",
+                slot.name().to_uppercase(),
+                index + 1,
+                Slot::LEARN_ORDER.len()
+            ));
+        }
+        if let Some((message, shown_at)) = &self.message
+            && shown_at.elapsed() < MESSAGE_DURATION
+        {
+            return Some(message.clone());
+        }
+        if !self.visible {
+            return None;
+        }
+
+        let press = state.last_key_press();
+        if press.seq == 0 {
+            return Some("Press any key".to_string());
+        }
+        let raw = press.raw();
+        Some(match press.key {
+            Some(key) => {
+                let slot = state
+                    .bindings
+                    .slot_for(key)
+                    .map(|slot| slot.name().to_uppercase())
+                    .unwrap_or_else(|| "unbound".to_string());
+                format!("{} | {} | {} | {}", key, raw, platform_keycode(key), slot)
+            }
+            None => format!("? | {} | no key name, can't be bound", raw),
+        })
+    }
+}
+
+// The key's code in this platform's native key codes
+fn platform_keycode(key: PhysicalKey) -> String {
+    if cfg!(target_os = "macos") {
+        format!("kVK 0x{:02X}", key.mac_vk())
+    } else {
+        format!("KEY {}", key.evdev())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{InputEvent, RawKey};
+
+    // One key per slot of `Slot::LEARN_ORDER`; Escape for pause
+    const LEARNED: [PhysicalKey; 9] = [
+        PhysicalKey::Z,
+        PhysicalKey::X,
+        PhysicalKey::ShiftLeft,
+        PhysicalKey::ArrowLeft,
+        PhysicalKey::ArrowUp,
+        PhysicalKey::ArrowDown,
+        PhysicalKey::ArrowRight,
+        PhysicalKey::Escape,
+        PhysicalKey::R,
+    ];
+
+    fn tap(state: &mut SharedState, inspector: &mut KeyInspector, key: PhysicalKey) {
+        for pressed in [true, false] {
+            let event = InputEvent::Key {
+                key,
+                pressed,
+                repeat: false,
+                raw: RawKey::Script,
+            };
+            crate::apply_input_event(state, event, None, false);
+        }
+        inspector.update(state);
+    }
+
+    // An inspector that saves to a file of its own under the temp dir
+    fn inspector(state: &SharedState, profile: Option<&str>, name: &str) -> KeyInspector {
+        let dir = std::env::temp_dir().join(format!("thkeyvis-{}-{}", name, std::process::id()));
+        let mut inspector = KeyInspector::new(state, profile.map(str::to_string));
+        inspector.config_path = Some(dir.join("config.toml"));
+        inspector
+    }
+
+    #[test]
+    fn learns_each_slot_in_order_then_saves() {
+        let mut state = SharedState::new();
+        let mut inspector = inspector(&state, None, "learn");
+        let path = inspector.config_path.clone().unwrap();
+
+        // Presses only count while learning
+        tap(&mut state, &mut inspector, PhysicalKey::Z);
+        assert_eq!(inspector.learning_slot(), None);
+
+        inspector.toggle_learning(&state);
+        for (index, (slot, key)) in Slot::LEARN_ORDER.into_iter().zip(LEARNED).enumerate() {
+            assert_eq!(inspector.learning_slot(), Some(slot));
+            // Keys without a name can't be bound and don't advance
+            let unmapped = InputEvent::Unmapped {
+                raw: RawKey::Script,
+                pressed: true,
+            };
+            crate::apply_input_event(&mut state, unmapped, None, false);
+            inspector.update(&mut state);
+            assert_eq!(inspector.learning_slot(), Some(slot));
+
+            tap(&mut state, &mut inspector, key);
+            if index + 1 < LEARNED.len() {
+                // Nothing is applied before the last slot
+                assert_eq!(state.bindings.key(Slot::Shot), PhysicalKey::Backspace);
+            }
+        }
+        assert_eq!(inspector.learning_slot(), None);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        let saved: toml::Table = toml::from_str(&text).unwrap();
+        for (slot, key) in Slot::LEARN_ORDER.into_iter().zip(LEARNED) {
+            assert_eq!(state.bindings.key(slot), key, "{}", slot.name());
+            assert_eq!(
+                saved["bindings"][slot.name()].as_str(),
+                Some(key.name()),
+                "{}",
+                slot.name()
+            );
+        }
+        let status = inspector.status_line(&state).unwrap();
+        assert!(status.starts_with("Saved bindings to "), "{status}");
+    }
+
+    #[test]
+    fn escape_cancels_and_keeps_the_bindings() {
+        let mut state = SharedState::new();
+        let mut inspector = inspector(&state, None, "cancel");
+        let before = state.bindings;
+
+        inspector.toggle_learning(&state);
+        tap(&mut state, &mut inspector, PhysicalKey::Z);
+        tap(&mut state, &mut inspector, PhysicalKey::X);
+        assert_eq!(inspector.learning_slot(), Some(Slot::Focus));
+        tap(&mut state, &mut inspector, PhysicalKey::Escape);
+
+        assert_eq!(inspector.learning_slot(), None);
+        assert!(
+            Slot::LEARN_ORDER
+                .into_iter()
+                .all(|slot| state.bindings.key(slot) == before.key(slot))
+        );
+        assert!(!inspector.config_path.as_ref().unwrap().exists());
+        assert_eq!(inspector.status_line(&state), None);
+
+        // The next run starts over from the first slot
+        inspector = KeyInspector::new(&state, None);
+        inspector.toggle_learning(&state);
+        assert_eq!(inspector.learning_slot(), Some(Slot::Shot));
+    }
+
+    #[test]
+    fn saves_to_the_selected_profile() {
+        let mut state = SharedState::new();
+        let mut inspector = inspector(&state, Some("practice"), "learn-profile");
+        let path = inspector.config_path.clone().unwrap();
+
+        inspector.toggle_learning(&state);
+        for key in LEARNED {
+            tap(&mut state, &mut inspector, key);
+        }
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        let saved: toml::Table = toml::from_str(&text).unwrap();
+        assert!(saved.get("bindings").is_none());
+        assert_eq!(
+            saved["profiles"]["practice"]["bindings"]["shot"].as_str(),
+            Some("z")
+        );
+        let status = inspector.status_line(&state).unwrap();
+        assert!(
+            status.starts_with("Saved bindings for profile practice to "),
+            "{status}"
+        );
+    }
+}
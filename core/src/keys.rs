@@ -0,0 +1,351 @@
+// Platform-neutral identity of a physical key, named after its position on a
+// US QWERTY keyboard. Everything past the input backends uses `PhysicalKey`,
+// so configs and replay files mean the same keys on macOS and Linux.
+
+// `repr(u8)` so per-key arrays can be indexed by `key as usize`, see `COUNT`
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PhysicalKey {
+    Escape,
+    Grave,
+    Digit1,
+    Digit2,
+    Digit3,
+    Digit4,
+    Digit5,
+    Digit6,
+    Digit7,
+    Digit8,
+    Digit9,
+    Digit0,
+    Minus,
+    Equal,
+    Backspace,
+    Tab,
+    Q,
+    W,
+    E,
+    R,
+    T,
+    Y,
+    U,
+    I,
+    O,
+    P,
+    LeftBracket,
+    RightBracket,
+    Backslash,
+    CapsLock,
+    A,
+    S,
+    D,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+    Semicolon,
+    Quote,
+    Enter,
+    ShiftLeft,
+    Z,
+    X,
+    C,
+    V,
+    B,
+    N,
+    M,
+    Comma,
+    Period,
+    Slash,
+    ShiftRight,
+    ControlLeft,
+    MetaLeft,
+    AltLeft,
+    Space,
+    AltRight,
+    MetaRight,
+    ControlRight,
+    ArrowLeft,
+    ArrowUp,
+    ArrowRight,
+    ArrowDown,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+}
+
+// One row per key with its code on every platform. Each conversion below
+// searches this table, so the two directions can't disagree.
+struct KeyCodes {
+    key: PhysicalKey,
+    // Used in configs and replay files
+    name: &'static str,
+    // kVK_* from Carbon's Events.h
+    mac_vk: u16,
+    // KEY_* from linux/input-event-codes.h
+    evdev: u16,
+    // VK_* from WinUser.h
+    windows_vk: u16,
+    // Set 1 make code; 0xE0xx for extended keys
+    windows_scan: u16,
+    rdev: rdev::Key,
+}
+
+macro_rules! key_table {
+    ($(($key:ident, $name:literal, $mac_vk:literal, $evdev:literal, $windows_vk:literal, $windows_scan:literal, $rdev:ident)),* $(,)?) => {
+        const KEY_TABLE: &[KeyCodes] = &[
+            $(KeyCodes {
+                key: PhysicalKey::$key,
+                name: $name,
+                mac_vk: $mac_vk,
+                evdev: $evdev,
+                windows_vk: $windows_vk,
+                windows_scan: $windows_scan,
+                rdev: rdev::Key::$rdev,
+            }),*
+        ];
+    };
+}
+
+#[rustfmt::skip]
+key_table![
+    (Escape, "esc", 0x35, 1, 0x1B, 0x01, Escape),
+    (Grave, "`", 0x32, 41, 0xC0, 0x29, BackQuote),
+    (Digit1, "1", 0x12, 2, 0x31, 0x02, Num1),
+    (Digit2, "2", 0x13, 3, 0x32, 0x03, Num2),
+    (Digit3, "3", 0x14, 4, 0x33, 0x04, Num3),
+    (Digit4, "4", 0x15, 5, 0x34, 0x05, Num4),
+    (Digit5, "5", 0x17, 6, 0x35, 0x06, Num5),
+    (Digit6, "6", 0x16, 7, 0x36, 0x07, Num6),
+    (Digit7, "7", 0x1A, 8, 0x37, 0x08, Num7),
+    (Digit8, "8", 0x1C, 9, 0x38, 0x09, Num8),
+    (Digit9, "9", 0x19, 10, 0x39, 0x0A, Num9),
+    (Digit0, "0", 0x1D, 11, 0x30, 0x0B, Num0),
+    (Minus, "-", 0x1B, 12, 0xBD, 0x0C, Minus),
+    (Equal, "=", 0x18, 13, 0xBB, 0x0D, Equal),
+    (Backspace, "backspace", 0x33, 14, 0x08, 0x0E, Backspace),
+    (Tab, "tab", 0x30, 15, 0x09, 0x0F, Tab),
+    (Q, "q", 0x0C, 16, 0x51, 0x10, KeyQ),
+    (W, "w", 0x0D, 17, 0x57, 0x11, KeyW),
+    (E, "e", 0x0E, 18, 0x45, 0x12, KeyE),
+    (R, "r", 0x0F, 19, 0x52, 0x13, KeyR),
+    (T, "t", 0x11, 20, 0x54, 0x14, KeyT),
+    (Y, "y", 0x10, 21, 0x59, 0x15, KeyY),
+    (U, "u", 0x20, 22, 0x55, 0x16, KeyU),
+    (I, "i", 0x22, 23, 0x49, 0x17, KeyI),
+    (O, "o", 0x1F, 24, 0x4F, 0x18, KeyO),
+    (P, "p", 0x23, 25, 0x50, 0x19, KeyP),
+    (LeftBracket, "[", 0x21, 26, 0xDB, 0x1A, LeftBracket),
+    (RightBracket, "]", 0x1E, 27, 0xDD, 0x1B, RightBracket),
+    (Backslash, "\\", 0x2A, 43, 0xDC, 0x2B, BackSlash),
+    (CapsLock, "capslock", 0x39, 58, 0x14, 0x3A, CapsLock),
+    (A, "a", 0x00, 30, 0x41, 0x1E, KeyA),
+    (S, "s", 0x01, 31, 0x53, 0x1F, KeyS),
+    (D, "d", 0x02, 32, 0x44, 0x20, KeyD),
+    (F, "f", 0x03, 33, 0x46, 0x21, KeyF),
+    (G, "g", 0x05, 34, 0x47, 0x22, KeyG),
+    (H, "h", 0x04, 35, 0x48, 0x23, KeyH),
+    (J, "j", 0x26, 36, 0x4A, 0x24, KeyJ),
+    (K, "k", 0x28, 37, 0x4B, 0x25, KeyK),
+    (L, "l", 0x25, 38, 0x4C, 0x26, KeyL),
+    (Semicolon, ";", 0x29, 39, 0xBA, 0x27, SemiColon),
+    (Quote, "'", 0x27, 40, 0xDE, 0x28, Quote),
+    (Enter, "enter", 0x24, 28, 0x0D, 0x1C, Return),
+    (ShiftLeft, "shift-left", 0x38, 42, 0xA0, 0x2A, ShiftLeft),
+    (Z, "z", 0x06, 44, 0x5A, 0x2C, KeyZ),
+    (X, "x", 0x07, 45, 0x58, 0x2D, KeyX),
+    (C, "c", 0x08, 46, 0x43, 0x2E, KeyC),
+    (V, "v", 0x09, 47, 0x56, 0x2F, KeyV),
+    (B, "b", 0x0B, 48, 0x42, 0x30, KeyB),
+    (N, "n", 0x2D, 49, 0x4E, 0x31, KeyN),
+    (M, "m", 0x2E, 50, 0x4D, 0x32, KeyM),
+    (Comma, ",", 0x2B, 51, 0xBC, 0x33, Comma),
+    (Period, ".", 0x2F, 52, 0xBE, 0x34, Dot),
+    (Slash, "/", 0x2C, 53, 0xBF, 0x35, Slash),
+    (ShiftRight, "shift-right", 0x3C, 54, 0xA1, 0x36, ShiftRight),
+    (ControlLeft, "control-left", 0x3B, 29, 0xA2, 0x1D, ControlLeft),
+    (MetaLeft, "meta-left", 0x37, 125, 0x5B, 0xE05B, MetaLeft),
+    (AltLeft, "alt-left", 0x3A, 56, 0xA4, 0x38, Alt),
+    (Space, "space", 0x31, 57, 0x20, 0x39, Space),
+    (AltRight, "alt-right", 0x3D, 100, 0xA5, 0xE038, AltGr),
+    (MetaRight, "meta-right", 0x36, 126, 0x5C, 0xE05C, MetaRight),
+    (ControlRight, "control-right", 0x3E, 97, 0xA3, 0xE01D, ControlRight),
+    (ArrowLeft, "left", 0x7B, 105, 0x25, 0xE04B, LeftArrow),
+    (ArrowUp, "up", 0x7E, 103, 0x26, 0xE048, UpArrow),
+    (ArrowRight, "right", 0x7C, 106, 0x27, 0xE04D, RightArrow),
+    (ArrowDown, "down", 0x7D, 108, 0x28, 0xE050, DownArrow),
+    (F1, "f1", 0x7A, 59, 0x70, 0x3B, F1),
+    (F2, "f2", 0x78, 60, 0x71, 0x3C, F2),
+    (F3, "f3", 0x63, 61, 0x72, 0x3D, F3),
+    (F4, "f4", 0x76, 62, 0x73, 0x3E, F4),
+    (F5, "f5", 0x60, 63, 0x74, 0x3F, F5),
+    (F6, "f6", 0x61, 64, 0x75, 0x40, F6),
+    (F7, "f7", 0x62, 65, 0x76, 0x41, F7),
+    (F8, "f8", 0x64, 66, 0x77, 0x42, F8),
+    (F9, "f9", 0x65, 67, 0x78, 0x43, F9),
+    (F10, "f10", 0x6D, 68, 0x79, 0x44, F10),
+    (F11, "f11", 0x67, 87, 0x7A, 0x57, F11),
+    (F12, "f12", 0x6F, 88, 0x7B, 0x58, F12),
+];
+
+impl PhysicalKey {
+    // Number of variants
+    pub const COUNT: usize = KEY_TABLE.len();
+
+    // In the order of the table above
+    pub fn all() -> impl Iterator<Item = Self> {
+        KEY_TABLE.iter().map(|row| row.key)
+    }
+
+    fn codes(self) -> &'static KeyCodes {
+        // Every variant has a row
+        KEY_TABLE.iter().find(|row| row.key == self).unwrap()
+    }
+
+    fn find(matches: impl Fn(&KeyCodes) -> bool) -> Option<Self> {
+        KEY_TABLE.iter().find(|row| matches(row)).map(|row| row.key)
+    }
+
+    pub fn name(self) -> &'static str {
+        self.codes().name
+    }
+
+    // Case-insensitive
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Self::find(|row| row.name == name)
+    }
+
+    pub fn mac_vk(self) -> u16 {
+        self.codes().mac_vk
+    }
+
+    pub fn from_mac_vk(code: u16) -> Option<Self> {
+        Self::find(|row| row.mac_vk == code)
+    }
+
+    pub fn evdev(self) -> u16 {
+        self.codes().evdev
+    }
+
+    pub fn from_evdev(code: u16) -> Option<Self> {
+        Self::find(|row| row.evdev == code)
+    }
+
+    pub fn windows_vk(self) -> u16 {
+        self.codes().windows_vk
+    }
+
+    pub fn from_windows_vk(code: u16) -> Option<Self> {
+        Self::find(|row| row.windows_vk == code)
+    }
+
+    pub fn windows_scan(self) -> u16 {
+        self.codes().windows_scan
+    }
+
+    pub fn from_windows_scan(code: u16) -> Option<Self> {
+        Self::find(|row| row.windows_scan == code)
+    }
+
+    // The same modifier on the other side of the keyboard
+    pub fn other_side(self) -> Option<Self> {
+        match self {
+            PhysicalKey::ShiftLeft => Some(PhysicalKey::ShiftRight),
+            PhysicalKey::ShiftRight => Some(PhysicalKey::ShiftLeft),
+            PhysicalKey::ControlLeft => Some(PhysicalKey::ControlRight),
+            PhysicalKey::ControlRight => Some(PhysicalKey::ControlLeft),
+            PhysicalKey::AltLeft => Some(PhysicalKey::AltRight),
+            PhysicalKey::AltRight => Some(PhysicalKey::AltLeft),
+            PhysicalKey::MetaLeft => Some(PhysicalKey::MetaRight),
+            PhysicalKey::MetaRight => Some(PhysicalKey::MetaLeft),
+            _ => None,
+        }
+    }
+
+    pub fn rdev(self) -> rdev::Key {
+        self.codes().rdev
+    }
+
+    pub fn from_rdev(key: rdev::Key) -> Option<Self> {
+        Self::find(|row| row.rdev == key)
+    }
+}
+
+impl std::fmt::Display for PhysicalKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_row_round_trips() {
+        for key in PhysicalKey::all() {
+            assert_eq!(PhysicalKey::from_name(key.name()), Some(key));
+            assert_eq!(PhysicalKey::from_mac_vk(key.mac_vk()), Some(key));
+            assert_eq!(PhysicalKey::from_evdev(key.evdev()), Some(key));
+            assert_eq!(PhysicalKey::from_windows_vk(key.windows_vk()), Some(key));
+            assert_eq!(
+                PhysicalKey::from_windows_scan(key.windows_scan()),
+                Some(key)
+            );
+            assert_eq!(PhysicalKey::from_rdev(key.rdev()), Some(key));
+        }
+    }
+
+    #[test]
+    fn rows_follow_the_enum() {
+        // Per-key arrays are indexed by `key as usize`
+        for (index, key) in PhysicalKey::all().enumerate() {
+            assert_eq!(key as usize, index);
+        }
+        assert_eq!(PhysicalKey::all().count(), PhysicalKey::COUNT);
+    }
+
+    #[test]
+    fn no_column_has_duplicates() {
+        fn assert_unique<T: PartialEq + std::fmt::Debug>(column: &str, values: Vec<T>) {
+            for (index, value) in values.iter().enumerate() {
+                assert!(
+                    !values[..index].contains(value),
+                    "duplicate {} {:?}",
+                    column,
+                    value
+                );
+            }
+        }
+
+        let rows = || KEY_TABLE.iter();
+        assert_unique("name", rows().map(|row| row.name).collect());
+        assert_unique("mac_vk", rows().map(|row| row.mac_vk).collect());
+        assert_unique("evdev", rows().map(|row| row.evdev).collect());
+        assert_unique("windows_vk", rows().map(|row| row.windows_vk).collect());
+        assert_unique("windows_scan", rows().map(|row| row.windows_scan).collect());
+        assert_unique("rdev", rows().map(|row| row.rdev).collect());
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        assert_eq!(PhysicalKey::from_name("Space"), Some(PhysicalKey::Space));
+        assert_eq!(
+            PhysicalKey::from_name("SHIFT-LEFT"),
+            Some(PhysicalKey::ShiftLeft)
+        );
+        assert_eq!(PhysicalKey::from_name("numpad-1"), None);
+    }
+}
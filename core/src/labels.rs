@@ -0,0 +1,332 @@
+// Keyboard layout name and per-key labels in shared memory. The layout
+// provider (Swift on macOS, xkb.rs on Linux) writes them from the monitor
+// process and the UI draws them. A layout is written as a whole under a
+// `SeqLock`, so a frame never shows a mix of the old and new layout.
+// Layout switches are kept in a short history for the UI and the log.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::keys::PhysicalKey;
+use crate::seqlock::SeqLock;
+
+// Max layout name length (null-terminated, so 63 bytes of text)
+const NAME_SIZE: usize = 64;
+// Number of layout switches kept
+const HISTORY_LEN: usize = 8;
+// Max label length (null-terminated, so 31 bytes of text, e.g. a few CJK
+// characters)
+const LABEL_SIZE: usize = 32;
+
+// Modifier layers a key can be labeled for, like the legends printed on a
+// keycap
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layer {
+    Base = 0,
+    Shift = 1,
+    // Option on macOS
+    AltGr = 2,
+}
+
+impl Layer {
+    pub const COUNT: usize = 3;
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0 => Some(Layer::Base),
+            1 => Some(Layer::Shift),
+            2 => Some(Layer::AltGr),
+            _ => None,
+        }
+    }
+}
+
+// What the active input source types, for the warning next to its name
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LayoutKind {
+    // Latin letters, typed directly
+    Latin = 0,
+    // Other letters typed directly (Cyrillic, Greek, ...), so labels don't
+    // match the game's key prompts
+    NonLatin = 1,
+    // An input method (Japanese, Chinese, ...) that may swallow key presses
+    InputMethod = 2,
+}
+
+impl LayoutKind {
+    pub fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0 => Some(LayoutKind::Latin),
+            1 => Some(LayoutKind::NonLatin),
+            2 => Some(LayoutKind::InputMethod),
+            _ => None,
+        }
+    }
+
+    // Badge text, if this kind deserves a warning
+    pub fn warning(self) -> Option<&'static str> {
+        match self {
+            LayoutKind::Latin => None,
+            LayoutKind::NonLatin => Some("NON-LATIN"),
+            LayoutKind::InputMethod => Some("IME ACTIVE"),
+        }
+    }
+}
+
+// One layout: a name and a label per physical key and layer. An empty label
+// means the key has none on that layer; without a base label the key is drawn
+// with its own name.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LayoutLabels {
+    name: [u8; NAME_SIZE],
+    kind: LayoutKind,
+    // Indexed by `PhysicalKey as usize`, then `Layer as usize`
+    labels: [[[u8; LABEL_SIZE]; Layer::COUNT]; PhysicalKey::COUNT],
+}
+
+impl LayoutLabels {
+    pub fn new() -> Self {
+        Self {
+            name: [0; NAME_SIZE],
+            kind: LayoutKind::Latin,
+            labels: [[[0; LABEL_SIZE]; Layer::COUNT]; PhysicalKey::COUNT],
+        }
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        copy_truncated(&mut self.name, name);
+    }
+
+    pub fn set_kind(&mut self, kind: LayoutKind) {
+        self.kind = kind;
+    }
+
+    // Labels made of control characters or whitespace (what Esc, Enter or
+    // Space type) are dropped
+    pub fn set_label(&mut self, key: PhysicalKey, layer: Layer, label: &str) {
+        let label = if label.chars().any(|c| c.is_control() || c.is_whitespace()) {
+            ""
+        } else {
+            label
+        };
+        copy_truncated(&mut self.labels[key as usize][layer as usize], label);
+    }
+
+    // e.g. "U.S." or "English (Colemak)"; empty until a provider reports one
+    pub fn name(&self) -> &str {
+        from_null_terminated(&self.name)
+    }
+
+    pub fn kind(&self) -> LayoutKind {
+        self.kind
+    }
+
+    pub fn label(&self, key: PhysicalKey, layer: Layer) -> &str {
+        from_null_terminated(&self.labels[key as usize][layer as usize])
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LayoutSwitch {
+    from: [u8; NAME_SIZE],
+    to: [u8; NAME_SIZE],
+    // Wall clock time
+    pub unix_millis: u64,
+}
+
+impl LayoutSwitch {
+    pub fn from(&self) -> &str {
+        from_null_terminated(&self.from)
+    }
+
+    pub fn to(&self) -> &str {
+        from_null_terminated(&self.to)
+    }
+
+    pub fn age(&self) -> Duration {
+        since_epoch().saturating_sub(Duration::from_millis(self.unix_millis))
+    }
+}
+
+// The latest layout switches, oldest overwritten first
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SwitchHistory {
+    switches: [LayoutSwitch; HISTORY_LEN],
+    // Switches since startup; also tells the UI when a new one happened
+    pub count: u64,
+}
+
+impl SwitchHistory {
+    fn new() -> Self {
+        Self {
+            switches: [LayoutSwitch {
+                from: [0; NAME_SIZE],
+                to: [0; NAME_SIZE],
+                unix_millis: 0,
+            }; HISTORY_LEN],
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, from: &str, to: &str) {
+        let switch = &mut self.switches[(self.count % HISTORY_LEN as u64) as usize];
+        copy_truncated(&mut switch.from, from);
+        copy_truncated(&mut switch.to, to);
+        switch.unix_millis = since_epoch().as_millis() as u64;
+        self.count += 1;
+    }
+
+    // Newest first
+    pub fn recent(&self) -> impl Iterator<Item = &LayoutSwitch> {
+        let kept = self.count.min(HISTORY_LEN as u64);
+        (1..=kept)
+            .map(move |back| &self.switches[((self.count - back) % HISTORY_LEN as u64) as usize])
+    }
+
+    pub fn latest(&self) -> Option<&LayoutSwitch> {
+        self.recent().next()
+    }
+
+    // `12:34:56.789 U.S. -> Colemak, ...`, newest first
+    pub fn describe(&self) -> String {
+        self.recent()
+            .map(|switch| {
+                format!(
+                    "{} {} -> {}",
+                    crate::logging::time_of_day(Duration::from_millis(switch.unix_millis)),
+                    switch.from(),
+                    switch.to()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+// The current layout as seen by both processes
+#[repr(C)]
+pub struct SharedLayout {
+    state: SeqLock<LayoutState>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LayoutState {
+    current: LayoutLabels,
+    history: SwitchHistory,
+}
+
+impl SharedLayout {
+    pub fn new() -> Self {
+        Self {
+            state: SeqLock::new(LayoutState {
+                current: LayoutLabels::new(),
+                history: SwitchHistory::new(),
+            }),
+        }
+    }
+
+    // Replace the whole layout. Returns whether that switched layouts.
+    pub fn publish(&mut self, layout: &LayoutLabels) -> bool {
+        self.update(|current| *current = *layout)
+    }
+
+    // Change part of the layout (e.g. one label); readers see all of the
+    // changes or none of them. A new name counts as a layout switch, except
+    // for the first one; returns whether this was one.
+    pub fn update(&mut self, change: impl FnOnce(&mut LayoutLabels)) -> bool {
+        let mut switched = false;
+        self.state.write(|state| {
+            let previous = state.current.name;
+            change(&mut state.current);
+            let from = from_null_terminated(&previous);
+            switched = !from.is_empty() && previous != state.current.name;
+            if switched {
+                state.history.push(from, state.current.name());
+            }
+        });
+        switched
+    }
+
+    // A consistent copy, taken once per frame
+    pub fn snapshot(&self) -> LayoutLabels {
+        self.state.read().current
+    }
+
+    pub fn history(&self) -> SwitchHistory {
+        self.state.read().history
+    }
+}
+
+fn since_epoch() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+// Cut at a character boundary, leaving room for the null terminator
+pub fn copy_truncated(buffer: &mut [u8], text: &str) {
+    let mut len = std::cmp::min(text.len(), buffer.len() - 1);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    buffer.fill(0);
+    buffer[..len].copy_from_slice(&text.as_bytes()[..len]);
+}
+
+pub fn from_null_terminated(buffer: &[u8]) -> &str {
+    let null_pos = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    // Only ever written from a `&str` cut at a character boundary
+    std::str::from_utf8(&buffer[..null_pos]).unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(name: &str) -> LayoutLabels {
+        let mut layout = LayoutLabels::new();
+        layout.set_name(name);
+        layout
+    }
+
+    #[test]
+    fn only_a_new_name_is_a_switch() {
+        let mut shared = Box::new(SharedLayout::new());
+        // The first layout
+        assert!(!shared.publish(&named("U.S.")));
+        assert!(!shared.update(|layout| layout.set_label(PhysicalKey::A, Layer::Base, "a")));
+        assert!(!shared.publish(&named("U.S.")));
+        assert!(shared.publish(&named("Colemak")));
+        assert!(shared.update(|layout| layout.set_name("U.S.")));
+
+        let history = shared.history();
+        assert_eq!(history.count, 2);
+        let recent: Vec<_> = history
+            .recent()
+            .map(|switch| (switch.from(), switch.to()))
+            .collect();
+        assert_eq!(recent, [("Colemak", "U.S."), ("U.S.", "Colemak")]);
+    }
+
+    #[test]
+    fn history_keeps_the_latest_switches() {
+        let mut shared = Box::new(SharedLayout::new());
+        shared.publish(&named("0"));
+        for i in 1..=HISTORY_LEN + 3 {
+            assert!(shared.publish(&named(&i.to_string())));
+        }
+
+        let history = shared.history();
+        assert_eq!(history.count, HISTORY_LEN as u64 + 3);
+        assert_eq!(history.recent().count(), HISTORY_LEN);
+        let latest = history.latest().unwrap();
+        assert_eq!((latest.from(), latest.to()), ("10", "11"));
+        let oldest = history.recent().last().unwrap();
+        assert_eq!((oldest.from(), oldest.to()), ("3", "4"));
+    }
+}
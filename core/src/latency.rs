@@ -0,0 +1,183 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Number of samples kept for percentile calculation (a few seconds of heavy input)
+const MAX_SAMPLES: usize = 1024;
+
+// How often the UI process prints the latency summary to stdout
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+// Monotonic clock in nanoseconds that is comparable between the monitor and UI
+// processes. `Instant` can't be shared through memory, but CLOCK_MONOTONIC is
+// the same clock for every process on the machine.
+pub fn monotonic_nanos() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+#[derive(Clone, Copy)]
+pub struct LatencySummary {
+    pub p50_micros: u64,
+    pub p95_micros: u64,
+    pub p99_micros: u64,
+    pub sample_count: usize,
+}
+
+// Tracks the time between the monitor process receiving an input event and the
+// UI process finishing the first frame that shows it
+pub struct LatencyTracker {
+    samples: VecDeque<u64>,
+    last_seen_seq: u64,
+    pending_event_nanos: Option<u64>,
+    // Summary is recomputed lazily, only after new samples arrive
+    cached_summary: Option<LatencySummary>,
+    summary_dirty: bool,
+    last_report: Instant,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::with_capacity(MAX_SAMPLES),
+            last_seen_seq: 0,
+            pending_event_nanos: None,
+            cached_summary: None,
+            summary_dirty: false,
+            last_report: Instant::now(),
+        }
+    }
+
+    // Call before drawing a frame with the event counters read from shared state
+    pub fn observe_state(&mut self, event_seq: u64, event_nanos: u64) {
+        if event_seq != self.last_seen_seq {
+            self.last_seen_seq = event_seq;
+            self.pending_event_nanos = Some(event_nanos);
+        }
+    }
+
+    // Call once the frame has been presented
+    pub fn frame_presented(&mut self, presented_nanos: u64) {
+        if let Some(event_nanos) = self.pending_event_nanos.take() {
+            self.record(presented_nanos.saturating_sub(event_nanos));
+        }
+    }
+
+    pub fn record(&mut self, latency_nanos: u64) {
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency_nanos / 1_000);
+        self.summary_dirty = true;
+    }
+
+    pub fn summary(&mut self) -> Option<LatencySummary> {
+        if self.summary_dirty {
+            self.summary_dirty = false;
+
+            let mut sorted: Vec<u64> = self.samples.iter().copied().collect();
+            sorted.sort_unstable();
+
+            self.cached_summary = Some(LatencySummary {
+                p50_micros: percentile(&sorted, 50),
+                p95_micros: percentile(&sorted, 95),
+                p99_micros: percentile(&sorted, 99),
+                sample_count: sorted.len(),
+            });
+        }
+        self.cached_summary
+    }
+
+    // Returns a summary when it's time to write it to the log
+    pub fn take_report(&mut self) -> Option<LatencySummary> {
+        if self.last_report.elapsed() < REPORT_INTERVAL {
+            return None;
+        }
+        self.last_report = Instant::now();
+        self.summary()
+    }
+}
+
+// Nearest-rank percentile over an already sorted slice, 0 when it's empty
+fn percentile(sorted: &[u64], percent: usize) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100);
+    sorted[rank.saturating_sub(1).min(sorted.len() - 1)]
+}
+
+impl LatencySummary {
+    pub fn format_millis(&self) -> String {
+        format!(
+            "p50/p95/p99: {:.1}/{:.1}/{:.1} ms",
+            self.p50_micros as f64 / 1000.0,
+            self.p95_micros as f64 / 1000.0,
+            self.p99_micros as f64 / 1000.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_uses_the_nearest_rank() {
+        assert_eq!(percentile(&[], 50), 0);
+        assert_eq!(percentile(&[7], 50), 7);
+        assert_eq!(percentile(&[7], 99), 7);
+
+        let sorted: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&sorted, 50), 50);
+        assert_eq!(percentile(&sorted, 95), 95);
+        assert_eq!(percentile(&sorted, 99), 99);
+
+        let sorted: Vec<u64> = (1..=10).collect();
+        assert_eq!(percentile(&sorted, 50), 5);
+        assert_eq!(percentile(&sorted, 99), 10);
+    }
+
+    #[test]
+    fn one_sample_per_event_from_state_to_frame() {
+        let mut tracker = LatencyTracker::new();
+        assert!(tracker.summary().is_none());
+
+        tracker.observe_state(1, 1_000_000);
+        tracker.frame_presented(3_000_000);
+        // Later frames without a new event add nothing
+        tracker.observe_state(1, 1_000_000);
+        tracker.frame_presented(4_000_000);
+        tracker.frame_presented(5_000_000);
+
+        let summary = tracker.summary().unwrap();
+        assert_eq!(summary.sample_count, 1);
+        assert_eq!(summary.p50_micros, 2_000);
+
+        // Several events before one frame count from the latest
+        tracker.observe_state(2, 6_000_000);
+        tracker.observe_state(3, 7_000_000);
+        tracker.frame_presented(8_000_000);
+        let summary = tracker.summary().unwrap();
+        assert_eq!(summary.sample_count, 2);
+        assert_eq!(summary.p50_micros, 1_000);
+        assert_eq!(summary.p99_micros, 2_000);
+    }
+
+    #[test]
+    fn keeps_the_latest_samples() {
+        let mut tracker = LatencyTracker::new();
+        for micros in 0..MAX_SAMPLES as u64 + 10 {
+            tracker.record(micros * 1_000);
+        }
+        let summary = tracker.summary().unwrap();
+        assert_eq!(summary.sample_count, MAX_SAMPLES);
+        // 10..1034 are left, the 1014th of them is 1023
+        assert_eq!(summary.p99_micros, 1023);
+    }
+}
@@ -1,7 +1,52 @@
 use raylib::prelude::*;
-use std::process::Command;
 use std::ptr;
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+mod bindings;
+mod config;
+#[cfg(target_os = "linux")]
+mod evdev;
+#[cfg(target_os = "macos")]
+mod event_tap;
+mod icons;
+#[cfg(target_os = "linux")]
+mod ime;
+mod input;
+mod inspector;
+pub mod keys;
+mod labels;
+mod latency;
+mod logging;
+mod pacing;
+mod permission;
+mod remap;
+mod seqlock;
+mod status;
+mod supervisor;
+mod toast;
+#[cfg(target_os = "linux")]
+mod xkb;
+
+use bindings::{Bindings, Slot};
+use config::{
+    Config, IconPosition, InputBackend, InputConfig, KeyStyle, LabelConfig, LabelSource,
+    ModifierConfig, PacingMode, ThemeConfig,
+};
+use icons::GameIcons;
+use input::{InputEvent, InputSource, RawKey};
+use inspector::KeyInspector;
+use keys::PhysicalKey;
+use labels::{Layer, LayoutKind, LayoutLabels, SharedLayout};
+use latency::{LatencySummary, LatencyTracker, monotonic_nanos};
+use logging::Role;
+use pacing::{FramePacer, FrameRatePolicy, WakeupReceiver, WakeupSender, wakeup_channel};
+use permission::PermissionStatus;
+use remap::RemapTable;
+use seqlock::SeqLock;
+use status::{Severity, StatusChannel, StatusCode, StatusEntry};
+use supervisor::{EXIT_FORK_FAILED, EXIT_OK, EXIT_PARENT_GONE};
+use toast::LayoutToast;
 
 // External Swift functions
 unsafe extern "C" {
@@ -12,170 +57,165 @@ unsafe extern "C" {
 #[repr(C)]
 pub struct SharedState {
     pub has_accessibility_permission: bool,
+    // Detailed state behind `has_accessibility_permission` (see permission.rs)
+    pub permission: PermissionStatus,
     // Request from Swift to close the application
     pub should_close: bool,
-    // Current keyboard layout name (null-terminated string, max 63 chars + null terminator)
-    pub current_layout_name: [u8; 64],
-    // Key states for the specific keys we monitor
+    // Current keyboard layout name and key labels (see labels.rs)
+    pub layout: SharedLayout,
+    // Key states for every physical key
     pub key_states: KeyStates,
+    // Physical key behind each drawn key (see bindings.rs)
+    pub bindings: Bindings,
+    // Latest key press, for the key inspector and learn mode
+    last_key_press: SeqLock<KeyPress>,
+    // Incremented by the monitor process for every key event it applies
+    pub input_event_seq: AtomicU64,
+    // CLOCK_MONOTONIC timestamp of the latest key event (see latency.rs)
+    pub input_event_nanos: AtomicU64,
+    // Incremented periodically by the monitor process so the UI can tell it
+    // is still alive and updating shared memory
+    pub monitor_heartbeat: AtomicU64,
+    // Linux: Fcitx or IBus has an input method on (see ime.rs), which the
+    // xkb keymap below it can't tell
+    pub input_method_active: AtomicBool,
+    // Errors and warnings to show in the UI (see status.rs)
+    pub status: StatusChannel,
 }
 
-// Specific keys we monitor based on SPECIFICATION.md
+// Every physical key is tracked so any of them can be bound to a slot
 #[repr(C)]
 pub struct KeyStates {
-    // Left side keys
-    pub esc: bool,       // keycode 53
-    pub key_0: bool,     // keycode 0 (A in QWERTY)
-    pub key_1: bool,     // keycode 1 (S in QWERTY)
-    pub key_2: bool,     // keycode 2 (D in QWERTY)
-    pub key_3: bool,     // keycode 3 (F in QWERTY)
-    pub backspace: bool, // keycode 51
-    // Right side keys
-    pub key_38: bool, // keycode 38 (J in QWERTY)
-    pub key_40: bool, // keycode 40 (K in QWERTY)
-    pub key_37: bool, // keycode 37 (L in QWERTY)
-    pub key_41: bool, // keycode 41 (; in QWERTY)
-    pub space: bool,  // keycode 49
-    // Key labels for current layout (null-terminated strings)
-    pub label_0: [u8; 8],  // Label for keycode 0 (A in QWERTY)
-    pub label_1: [u8; 8],  // Label for keycode 1 (S in QWERTY)
-    pub label_2: [u8; 8],  // Label for keycode 2 (D in QWERTY)
-    pub label_3: [u8; 8],  // Label for keycode 3 (F in QWERTY)
-    pub label_38: [u8; 8], // Label for keycode 38 (J in QWERTY)
-    pub label_40: [u8; 8], // Label for keycode 40 (K in QWERTY)
-    pub label_37: [u8; 8], // Label for keycode 37 (L in QWERTY)
-    pub label_41: [u8; 8], // Label for keycode 41 (; in QWERTY)
+    // Indexed by `PhysicalKey as usize`
+    pub pressed: [bool; PhysicalKey::COUNT],
+    // CLOCK_MONOTONIC time of the latest autorepeat, per key
+    pub repeat_nanos: [u64; PhysicalKey::COUNT],
+}
+
+// Written by the monitor process on every key press
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct KeyPress {
+    // Number of presses so far, so readers can tell a new one
+    pub seq: u64,
+    // `None` for keys without a `PhysicalKey`
+    pub key: Option<PhysicalKey>,
+    // What the backend reported, e.g. "rdev KeyA" (null-terminated string)
+    raw: [u8; 96],
+}
+
+impl KeyPress {
+    pub fn raw(&self) -> &str {
+        labels::from_null_terminated(&self.raw)
+    }
 }
 
 impl SharedState {
     pub fn new() -> Self {
         Self {
             has_accessibility_permission: false,
+            permission: PermissionStatus::Unknown,
             should_close: false,
-            current_layout_name: [0; 64],
+            layout: SharedLayout::new(),
             key_states: KeyStates::new(),
+            bindings: Bindings::new(),
+            last_key_press: SeqLock::new(KeyPress {
+                seq: 0,
+                key: None,
+                raw: [0; 96],
+            }),
+            input_event_seq: AtomicU64::new(0),
+            input_event_nanos: AtomicU64::new(0),
+            monitor_heartbeat: AtomicU64::new(0),
+            input_method_active: AtomicBool::new(false),
+            status: StatusChannel::new(),
         }
     }
 
-    pub fn set_layout_name(&mut self, name: &str) {
-        // Clear the array first
-        self.current_layout_name = [0; 64];
+    // Stamp the latest key event so the UI can measure event-to-draw latency.
+    // The sequence number is published after the timestamp, so a reader that
+    // sees a new sequence number also sees its timestamp (or a newer one).
+    pub fn record_input_event(&mut self) {
+        let now = monotonic_nanos();
+        self.input_event_nanos.store(now, Ordering::Relaxed);
+        self.input_event_seq.fetch_add(1, Ordering::Release);
+    }
 
-        // Copy the string bytes, ensuring we don't exceed the buffer size
-        let bytes = name.as_bytes();
-        let copy_len = std::cmp::min(bytes.len(), 63); // Leave room for null terminator
-        self.current_layout_name[..copy_len].copy_from_slice(&bytes[..copy_len]);
-        // Array is already null-terminated due to initialization with zeros
+    // Sequence number and timestamp of the latest key event
+    pub fn input_event(&self) -> (u64, u64) {
+        let seq = self.input_event_seq.load(Ordering::Acquire);
+        (seq, self.input_event_nanos.load(Ordering::Relaxed))
     }
 
-    pub fn get_layout_name(&self) -> String {
-        // Find the null terminator
-        let null_pos = self
-            .current_layout_name
-            .iter()
-            .position(|&b| b == 0)
-            .unwrap_or(64);
-        String::from_utf8_lossy(&self.current_layout_name[..null_pos]).to_string()
-    }
-
-    pub fn set_key_label(&mut self, key_position: &str, label: &str) {
-        let target_array = match key_position {
-            "a" => &mut self.key_states.label_0,  // A key -> keycode 0
-            "s" => &mut self.key_states.label_1,  // S key -> keycode 1
-            "d" => &mut self.key_states.label_2,  // D key -> keycode 2
-            "f" => &mut self.key_states.label_3,  // F key -> keycode 3
-            "j" => &mut self.key_states.label_38, // J key -> keycode 38
-            "k" => &mut self.key_states.label_40, // K key -> keycode 40
-            "l" => &mut self.key_states.label_37, // L key -> keycode 37
-            ";" => &mut self.key_states.label_41, // ; key -> keycode 41
-            _ => return,
-        };
+    pub fn record_key_press(&mut self, key: Option<PhysicalKey>, raw: &RawKey) {
+        // Device names may be cut anywhere
+        let raw = raw.to_string();
+        self.last_key_press.write(|press| {
+            press.key = key;
+            labels::copy_truncated(&mut press.raw, &raw);
+            press.seq = press.seq.wrapping_add(1);
+        });
+    }
 
-        // Clear the array first
-        *target_array = [0; 8];
-
-        // Copy the string bytes, ensuring we don't exceed the buffer size
-        let bytes = label.as_bytes();
-        let copy_len = std::cmp::min(bytes.len(), 7); // Leave room for null terminator
-        target_array[..copy_len].copy_from_slice(&bytes[..copy_len]);
-    }
-
-    pub fn get_key_label(&self, key_position: &str) -> String {
-        let source_array = match key_position {
-            "a" => &self.key_states.label_0,  // A key -> keycode 0
-            "s" => &self.key_states.label_1,  // S key -> keycode 1
-            "d" => &self.key_states.label_2,  // D key -> keycode 2
-            "f" => &self.key_states.label_3,  // F key -> keycode 3
-            "j" => &self.key_states.label_38, // J key -> keycode 38
-            "k" => &self.key_states.label_40, // K key -> keycode 40
-            "l" => &self.key_states.label_37, // L key -> keycode 37
-            ";" => &self.key_states.label_41, // ; key -> keycode 41
-            _ => return "?".to_string(),
-        };
+    // A consistent copy of the latest key press
+    pub fn last_key_press(&self) -> KeyPress {
+        self.last_key_press.read()
+    }
 
-        let null_pos = source_array.iter().position(|&b| b == 0).unwrap_or(8);
-        String::from_utf8_lossy(&source_array[..null_pos]).to_string()
+    // Feed one permission check result (`None` = couldn't tell) through the
+    // state machine
+    pub fn set_permission(&mut self, granted: Option<bool>) {
+        let status = self.permission.next(granted);
+        if status != self.permission {
+            log::info!(from:? = self.permission, to:? = status; "Permission state changed");
+        }
+        self.permission = status;
+        self.has_accessibility_permission = status == PermissionStatus::Granted;
+    }
+}
+
+impl Default for SharedState {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl KeyStates {
     pub fn new() -> Self {
         Self {
-            esc: false,
-            key_0: false,
-            key_1: false,
-            key_2: false,
-            key_3: false,
-            backspace: false,
-            key_38: false,
-            key_40: false,
-            key_37: false,
-            key_41: false,
-            space: false,
-            label_0: [0; 8],
-            label_1: [0; 8],
-            label_2: [0; 8],
-            label_3: [0; 8],
-            label_38: [0; 8],
-            label_40: [0; 8],
-            label_37: [0; 8],
-            label_41: [0; 8],
-        }
-    }
-
-    pub fn set_key_state(&mut self, keycode: u32, pressed: bool) {
-        match keycode {
-            53 => self.esc = pressed,       // ESC
-            0 => self.key_0 = pressed,      // keycode 0 (A in QWERTY)
-            1 => self.key_1 = pressed,      // keycode 1 (S in QWERTY)
-            2 => self.key_2 = pressed,      // keycode 2 (D in QWERTY)
-            3 => self.key_3 = pressed,      // keycode 3 (F in QWERTY)
-            51 => self.backspace = pressed, // Backspace
-            38 => self.key_38 = pressed,    // keycode 38 (J in QWERTY)
-            40 => self.key_40 = pressed,    // keycode 40 (K in QWERTY)
-            37 => self.key_37 = pressed,    // keycode 37 (L in QWERTY)
-            41 => self.key_41 = pressed,    // keycode 41 (; in QWERTY)
-            49 => self.space = pressed,     // Space
-            _ => {}                         // Ignore other keys
-        }
-    }
-
-    pub fn get_key_state(&self, keycode: u32) -> bool {
-        match keycode {
-            53 => self.esc,
-            0 => self.key_0,
-            1 => self.key_1,
-            2 => self.key_2,
-            3 => self.key_3,
-            51 => self.backspace,
-            38 => self.key_38,
-            40 => self.key_40,
-            37 => self.key_37,
-            41 => self.key_41,
-            49 => self.space,
-            _ => false,
+            pressed: [false; PhysicalKey::COUNT],
+            repeat_nanos: [0; PhysicalKey::COUNT],
         }
     }
+
+    pub fn set_key_state(&mut self, key: PhysicalKey, pressed: bool) {
+        self.pressed[key as usize] = pressed;
+    }
+
+    pub fn release_all(&mut self) {
+        self.pressed = [false; PhysicalKey::COUNT];
+        self.repeat_nanos = [0; PhysicalKey::COUNT];
+    }
+
+    pub fn mark_repeat(&mut self, key: PhysicalKey) {
+        self.repeat_nanos[key as usize] = monotonic_nanos();
+    }
+
+    // Whether `key` autorepeated within the last `window_nanos`
+    pub fn is_repeating(&self, key: PhysicalKey, window_nanos: u64) -> bool {
+        let last_repeat = self.repeat_nanos[key as usize];
+        last_repeat != 0 && monotonic_nanos().saturating_sub(last_repeat) < window_nanos
+    }
+
+    pub fn get_key_state(&self, key: PhysicalKey) -> bool {
+        self.pressed[key as usize]
+    }
+}
+
+impl Default for KeyStates {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 // Legacy Swift FFI compatibility
@@ -195,28 +235,120 @@ type PermissionMonitoringCallback = unsafe extern "C" fn();
 // This is the new main entry point that forks early with permission monitoring callback
 #[unsafe(no_mangle)]
 pub extern "C" fn rust_main_with_callback(callback: Option<PermissionMonitoringCallback>) {
+    logging::init();
+
+    // Load the config before forking so both processes agree on it. It lives
+    // for the rest of the process, including UI restarts.
+    let config: &'static Config = Box::leak(Box::new(Config::load()));
+    logging::configure(&config.log);
+
     // Create anonymous shared memory (no physical file)
     let shared_state = create_shared_memory();
+    unsafe {
+        (*shared_state).bindings =
+            Bindings::from_config(&config.bindings, config.modifiers.merge_sides);
+    }
+
+    // In event-driven mode the monitor process wakes the UI through a pipe
+    let wakeup = if config.frame.pacing == PacingMode::EventDriven {
+        match wakeup_channel() {
+            Ok(channel) => Some(channel),
+            Err(error) => {
+                log::error!(error:% = error; "Failed to create wake-up pipe, event-driven pacing disabled");
+                unsafe {
+                    (*shared_state).status.report(
+                        StatusCode::WakeupUnavailable,
+                        &format!("Event-driven redraw unavailable: {}", error),
+                    );
+                }
+                None
+            }
+        }
+    } else {
+        None
+    };
 
     // Set global pointer for Swift FFI access
     unsafe {
         SHARED_STATE_PTR = shared_state;
     }
 
-    // Fork the process BEFORE any UI initialization
+    // Fork the process BEFORE any UI initialization, and before this process
+    // starts any threads (see supervisor::supervise)
+    let monitor_pid = unsafe { libc::getpid() };
+    let Some(supervisor_pid) = spawn_supervisor_process(shared_state, config, wakeup, monitor_pid)
+    else {
+        std::process::exit(EXIT_FORK_FAILED);
+    };
+
+    // Parent process: Run key monitoring and permission checking
+    logging::set_role(Role::Monitor);
+    run_key_monitor_process(
+        shared_state,
+        supervisor_pid,
+        callback,
+        wakeup.map(|(sender, _)| sender),
+        config,
+    );
+}
+
+// Fork the single-threaded process that starts and restarts the UI process.
+// Returns its pid in the parent; never returns in the child.
+fn spawn_supervisor_process(
+    shared_state: *mut SharedState,
+    config: &'static Config,
+    wakeup: Option<(WakeupSender, WakeupReceiver)>,
+    monitor_pid: i32,
+) -> Option<i32> {
     match unsafe { libc::fork() } {
         -1 => {
-            eprintln!("Failed to fork process");
-            std::process::exit(1);
+            log::error!(error:% = std::io::Error::last_os_error(); "Failed to fork process");
+            None
         }
         0 => {
-            // Child process: Run pure Rust UI
-            run_ui_process(shared_state);
+            logging::set_role(Role::Supervisor);
+            supervisor::watch_parent();
+            // Only the monitor process writes; UI processes get the read end
+            let wakeup_receiver = wakeup.map(|(sender, receiver)| {
+                sender.close();
+                receiver
+            });
+            let supervisor_pid = unsafe { libc::getpid() };
+            let spawn = || spawn_ui_process(shared_state, config, wakeup_receiver, supervisor_pid);
+            let Some(ui_pid) = spawn() else {
+                std::process::exit(EXIT_FORK_FAILED);
+            };
+            log::info!(ui_pid = ui_pid; "Supervising UI process");
+            let exit_code = supervisor::supervise(ui_pid, monitor_pid, spawn, || unsafe {
+                (*shared_state).should_close
+            });
+            std::process::exit(exit_code);
         }
-        child_pid => {
-            // Parent process: Run key monitoring and permission checking
-            run_key_monitor_process(shared_state, child_pid, callback);
+        supervisor_pid => Some(supervisor_pid),
+    }
+}
+
+// Fork a UI process from the supervisor process. Returns its pid in the
+// parent; never returns in the child.
+fn spawn_ui_process(
+    shared_state: *mut SharedState,
+    config: &'static Config,
+    wakeup: Option<WakeupReceiver>,
+    supervisor_pid: i32,
+) -> Option<i32> {
+    match unsafe { libc::fork() } {
+        -1 => {
+            log::error!(error:% = std::io::Error::last_os_error(); "Failed to fork process");
+            None
+        }
+        0 => {
+            // Child process: Run pure Rust UI
+            logging::set_role(Role::Ui);
+            supervisor::watch_parent();
+            let exit_code = run_ui_process(shared_state, config, wakeup, supervisor_pid);
+            std::process::exit(exit_code);
         }
+        child_pid => Some(child_pid),
     }
 }
 
@@ -261,102 +393,402 @@ fn create_shared_memory() -> *mut SharedState {
 
 fn run_key_monitor_process(
     shared_state: *mut SharedState,
-    child_pid: i32,
+    supervisor_pid: i32,
     callback: Option<PermissionMonitoringCallback>,
+    wakeup: Option<WakeupSender>,
+    config: &'static Config,
 ) {
-    println!("Parent process: Starting key monitoring...");
+    log::info!(supervisor_pid = supervisor_pid; "Starting key monitoring");
 
-    // Set up signal handler to clean up when child exits
-    extern "C" fn signal_handler(_: i32) {
-        std::process::exit(0);
-    }
-    unsafe {
-        libc::signal(libc::SIGCHLD, signal_handler as usize);
-    }
+    // The supervisor process restarts the UI when it crashes and exits once
+    // it closes normally; the whole application exits with it
+    std::thread::spawn(move || {
+        std::process::exit(supervisor::wait_for_supervisor(supervisor_pid));
+    });
 
     // Start permission monitoring - use Swift callback if provided, otherwise use Rust fallback
     if let Some(permission_callback) = callback {
-        println!("Parent process: Starting Swift permission monitoring...");
+        log::info!("Starting Swift permission monitoring");
         unsafe {
             permission_callback();
         }
     } else {
-        println!("Parent process: Starting fallback Rust permission monitoring...");
+        log::info!("Starting fallback Rust permission monitoring");
         // Start a separate thread for permission checking (fallback)
         let permission_shared_state = shared_state as usize; // Convert to usize for thread safety
         std::thread::spawn(move || {
             let shared_ptr = permission_shared_state as *mut SharedState;
+            let provider = permission::platform_provider();
             loop {
-                // Check accessibility permission every 500ms
-                let has_permission = check_accessibility_permission();
+                // Check permission every 500ms
+                let granted = provider.check();
                 unsafe {
-                    (*shared_ptr).has_accessibility_permission = has_permission;
+                    (*shared_ptr).set_permission(granted);
                 }
                 std::thread::sleep(std::time::Duration::from_millis(500));
             }
         });
     }
 
-    // Start rdev listener - this is the parent process so no thread safety issues
-    if let Err(error) = rdev::listen(move |event| {
+    // Let the UI notice if this process stops updating shared memory
+    let heartbeat_shared_state = shared_state as usize; // Convert to usize for thread safety
+    std::thread::spawn(move || {
+        let shared_ptr = heartbeat_shared_state as *mut SharedState;
+        loop {
+            let heartbeat = unsafe { &(*shared_ptr).monitor_heartbeat };
+            heartbeat.fetch_add(1, Ordering::Release);
+            std::thread::sleep(HEARTBEAT_INTERVAL);
+        }
+    });
+
+    #[cfg(target_os = "macos")]
+    spawn_listener_watchdog(shared_state);
+    #[cfg(target_os = "linux")]
+    spawn_input_method_watch(shared_state);
+
+    // Start the key listener - this is the parent process so no thread safety issues.
+    // Never returns: the supervisor thread ends the process once the UI closes.
+    run_listener_loop(shared_state, wakeup, config);
+}
+
+// How often the monitor process bumps `monitor_heartbeat`
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(250);
+// The UI reports the monitor as unresponsive after this long without a beat
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Listener retry backoff while permission is missing: 250ms, 500ms, ... 5s
+const LISTENER_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const LISTENER_MAX_BACKOFF: Duration = Duration::from_secs(5);
+// A listener that ran this long before stopping counts as a fresh failure
+const LISTENER_STABLE_RUNTIME: Duration = Duration::from_secs(5);
+
+// The backend to actually use for `[input] backend`
+fn resolve_input_backend(backend: InputBackend) -> InputBackend {
+    match backend {
+        #[cfg(target_os = "linux")]
+        InputBackend::Auto => {
+            if permission::platform_provider().check() == Some(true) {
+                InputBackend::Evdev
+            } else {
+                InputBackend::Rdev
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        InputBackend::Auto => InputBackend::Rdev,
+        #[cfg(not(target_os = "linux"))]
+        InputBackend::Evdev => {
+            log::warn!("The evdev input backend is only available on Linux, using rdev");
+            InputBackend::Rdev
+        }
+        backend => backend,
+    }
+}
+
+fn create_input_source(
+    backend: InputBackend,
+    input: &InputConfig,
+) -> Result<Box<dyn InputSource>, String> {
+    match backend {
+        #[cfg(target_os = "linux")]
+        InputBackend::Evdev => Ok(Box::new(input::EvdevSource {
+            devices: input.devices.clone(),
+        })),
+        InputBackend::Replay => {
+            let path = input
+                .replay_file
+                .as_ref()
+                .ok_or("[input] replay_file is not set")?;
+            Ok(Box::new(input::ReplaySource::open(path)?))
+        }
+        #[cfg(target_os = "macos")]
+        _ => Ok(Box::new(input::EventTapSource)),
+        #[cfg(not(target_os = "macos"))]
+        _ => Ok(Box::new(input::RdevSource)),
+    }
+}
+
+// Listeners return (or fail right away) when permission is missing at
+// launch or revoked mid-session. Keep recreating the listener instead of
+// leaving the window dark until the app is restarted.
+fn run_listener_loop(
+    shared_state: *mut SharedState,
+    wakeup: Option<WakeupSender>,
+    config: &Config,
+) -> ! {
+    let input = &config.input;
+    let backend = resolve_input_backend(input.backend);
+    log::info!(backend:? = backend; "Starting key listener");
+    if backend == InputBackend::Rdev && !input.devices.is_empty() {
+        log::warn!("[input] devices only works with the evdev backend, listening to all keyboards");
+    }
+    let show_repeats = input.show_repeats;
+    let mut backoff = LISTENER_INITIAL_BACKOFF;
+
+    // Labels and the layout name come from Swift on macOS; on Linux they
+    // follow the xkb keymap. Kept across listener restarts so the active
+    // layout isn't forgotten.
+    #[cfg(target_os = "linux")]
+    let xkb_layout = std::rc::Rc::new(std::cell::RefCell::new(load_xkb_layout(
+        unsafe { &mut *shared_state },
+        &config.xkb,
+    )));
+
+    loop {
+        let started_at = Instant::now();
+        let result = create_input_source(backend, input).and_then(|mut source| {
+            let shared_state = shared_state as usize; // Convert to usize so the sink is 'static
+            #[cfg(target_os = "linux")]
+            let xkb_layout = xkb_layout.clone();
+            source.run(Box::new(move |event| {
+                let state = unsafe { &mut *(shared_state as *mut SharedState) };
+                #[cfg(target_os = "linux")]
+                if let Some(layout) = xkb_layout.borrow_mut().as_mut() {
+                    update_xkb_layout(layout, state, &event);
+                }
+                apply_input_event(state, event, wakeup, show_repeats);
+            }))
+        });
+
         let state = unsafe { &mut *shared_state };
+        match result {
+            Ok(()) => {
+                log::warn!("Key listener stopped");
+                state
+                    .status
+                    .report(StatusCode::ListenerFailed, "Key listener stopped, retrying");
+            }
+            Err(error) => {
+                log::warn!(error:% = error, retry_ms = backoff.as_millis() as u64; "Key listener failed");
+                state.status.report(
+                    StatusCode::ListenerFailed,
+                    &format!("Key listener failed ({}), retrying", error),
+                );
+            }
+        }
 
-        match event.event_type {
-            rdev::EventType::KeyPress(key) => {
-                if let Some(keycode) = rdev_key_to_keycode(key) {
-                    state.key_states.set_key_state(keycode, true);
+        // Releases that happened while the listener was down were never seen
+        release_all_keys(state, wakeup);
+
+        if started_at.elapsed() >= LISTENER_STABLE_RUNTIME {
+            backoff = LISTENER_INITIAL_BACKOFF;
+        }
+        wait_before_listener_retry(shared_state, backoff);
+        backoff = (backoff * 2).min(LISTENER_MAX_BACKOFF);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn load_xkb_layout(state: &mut SharedState, config: &config::XkbConfig) -> Option<xkb::XkbLayout> {
+    match xkb::XkbLayout::new(config) {
+        Ok(layout) => {
+            log::info!(
+                layout:% = layout.layout_name(),
+                kind:? = layout.kind(),
+                layouts = layout.num_layouts();
+                "Loaded xkb keymap"
+            );
+            if layout.num_layouts() > 1 {
+                log::info!(assumed:% = layout.layout_name(); "The active layout can't be read at startup, assuming the first one until a switch");
+            }
+            // The first layout isn't a switch
+            layout.publish(state);
+            Some(layout)
+        }
+        Err(error) => {
+            log::warn!(error:% = error; "No xkb keymap, key labels stay QWERTY");
+            None
+        }
+    }
+}
+
+// Track layout switches done with keys, before the event reaches the UI
+#[cfg(target_os = "linux")]
+fn update_xkb_layout(layout: &mut xkb::XkbLayout, state: &mut SharedState, event: &InputEvent) {
+    let (code, pressed) = match event {
+        // xkb must not see autorepeats
+        InputEvent::Key { repeat: true, .. } => return,
+        // Modifiers it thinks are held would change what the next keys do
+        InputEvent::ReleaseAll => {
+            layout.reset();
+            return;
+        }
+        InputEvent::Key {
+            raw: RawKey::Evdev { code, .. },
+            pressed,
+            ..
+        }
+        | InputEvent::Unmapped {
+            raw: RawKey::Evdev { code, .. },
+            pressed,
+        } => (*code, *pressed),
+        InputEvent::Key { key, pressed, .. } => (key.evdev(), *pressed),
+        InputEvent::Unmapped { .. } | InputEvent::Layout { .. } => return,
+    };
+
+    if layout.key_event(code, pressed) && layout.publish(state) {
+        log_layout_switch(&state.layout);
+    }
+}
+
+// Log the switch that just happened with the recent ones, to spot accidental
+// toggles
+fn log_layout_switch(layout: &SharedLayout) {
+    let current = layout.snapshot();
+    let history = layout.history();
+    let last_minute = history
+        .recent()
+        .take_while(|switch| switch.age() < Duration::from_secs(60))
+        .count();
+    log::info!(
+        from:% = history.latest().map_or("", |switch| switch.from()),
+        to:% = current.name(),
+        kind:? = current.kind(),
+        switches_last_minute = last_minute,
+        history:% = history.describe();
+        "Keyboard layout switched"
+    );
+}
+
+// Shared by every input source
+fn apply_input_event(
+    state: &mut SharedState,
+    event: InputEvent,
+    wakeup: Option<WakeupSender>,
+    show_repeats: bool,
+) {
+    match event {
+        InputEvent::Key {
+            key,
+            pressed,
+            repeat,
+            raw,
+        } => {
+            // Getting events means the listener works again
+            if state.status.is_active(StatusCode::ListenerFailed) {
+                state.status.clear(StatusCode::ListenerFailed);
+            }
+
+            if repeat {
+                // The key is already shown as held; a repeat changes nothing
+                // unless repeats are visualized
+                if !show_repeats {
+                    return;
                 }
+                state.key_states.mark_repeat(key);
+            }
+            if pressed && !repeat {
+                state.record_key_press(Some(key), &raw);
             }
-            rdev::EventType::KeyRelease(key) => {
-                if let Some(keycode) = rdev_key_to_keycode(key) {
-                    state.key_states.set_key_state(keycode, false);
+            state.key_states.set_key_state(key, pressed);
+            state.record_input_event();
+            if let Some(wakeup) = wakeup {
+                wakeup.notify();
+            }
+        }
+        InputEvent::Unmapped { raw, pressed } => {
+            if pressed {
+                state.record_key_press(None, &raw);
+                if let Some(wakeup) = wakeup {
+                    wakeup.notify();
                 }
             }
-            _ => {} // Ignore other events
         }
-    }) {
-        eprintln!("Key monitoring error: {:?}", error);
+        InputEvent::ReleaseAll => release_all_keys(state, wakeup),
+        InputEvent::Layout { name } => {
+            if state.layout.update(|layout| layout.set_name(&name)) {
+                log_layout_switch(&state.layout);
+            }
+            if let Some(wakeup) = wakeup {
+                wakeup.notify();
+            }
+        }
     }
+}
 
-    // Wait for child to exit
-    let mut status = 0;
-    unsafe {
-        libc::waitpid(child_pid, &mut status, 0);
+fn release_all_keys(state: &mut SharedState, wakeup: Option<WakeupSender>) {
+    state.key_states.release_all();
+    state.record_input_event();
+    if let Some(wakeup) = wakeup {
+        wakeup.notify();
     }
 }
 
-// Check accessibility permission using system call
-// Convert rdev Key to macOS keycode
-fn rdev_key_to_keycode(key: rdev::Key) -> Option<u32> {
-    match key {
-        rdev::Key::Escape => Some(53),
-        rdev::Key::KeyA => Some(0),
-        rdev::Key::KeyS => Some(1), // This is the R position in Colemak
-        rdev::Key::KeyD => Some(2), // This is the S position in Colemak
-        rdev::Key::KeyF => Some(3), // This is the T position in Colemak
-        rdev::Key::Backspace => Some(51),
-        rdev::Key::KeyJ => Some(38), // This is the N position in Colemak
-        rdev::Key::KeyK => Some(40), // This is the E position in Colemak
-        rdev::Key::KeyL => Some(37), // This is the I position in Colemak
-        rdev::Key::SemiColon => Some(41), // This is the O position in Colemak
-        rdev::Key::Space => Some(49),
-        _ => None,
+// Sleep for `backoff`, but return early once permission is granted
+fn wait_before_listener_retry(shared_state: *const SharedState, backoff: Duration) {
+    let had_permission = unsafe { (*shared_state).has_accessibility_permission };
+    let deadline = Instant::now() + backoff;
+
+    while Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+        let has_permission = unsafe { (*shared_state).has_accessibility_permission };
+        if has_permission && !had_permission {
+            log::info!("Permission granted, restarting key listener");
+            return;
+        }
     }
 }
 
-fn check_accessibility_permission() -> bool {
-    use std::process::Command;
-
-    // Use AppleScript to check accessibility permission
-    let output = Command::new("osascript")
-        .arg("-e")
-        .arg("tell application \"System Events\" to return true")
-        .output();
+// How often Fcitx and IBus are asked for the active input method
+#[cfg(target_os = "linux")]
+const INPUT_METHOD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// macOS reports input methods with the input source; on Linux they sit above
+// the xkb keymap and are asked for separately
+#[cfg(target_os = "linux")]
+fn spawn_input_method_watch(shared_state: *mut SharedState) {
+    let watch_shared_state = shared_state as usize; // Convert to usize for thread safety
+    std::thread::spawn(move || {
+        let Some(watch) = ime::InputMethodWatch::new() else {
+            log::info!("Neither fcitx5-remote nor ibus found, input methods go undetected");
+            return;
+        };
+        let shared_ptr = watch_shared_state as *const SharedState;
+        let mut active: Option<String> = None;
+        loop {
+            let engine = watch.poll();
+            if engine != active {
+                match &engine {
+                    Some(engine) => log::info!(engine = engine.as_str(); "Input method on"),
+                    None => log::info!("Input method off"),
+                }
+                let flag = unsafe { &(*shared_ptr).input_method_active };
+                flag.store(engine.is_some(), Ordering::Relaxed);
+                active = engine;
+            }
+            std::thread::sleep(INPUT_METHOD_POLL_INTERVAL);
+        }
+    });
+}
 
-    match output {
-        Ok(result) => result.status.success(),
-        Err(_) => false,
+// On macOS a revoked permission silently disables the event tap while its
+// run loop keeps blocking in CFRunLoopRun. When the permission comes back,
+// stop the main run loop; the source removes the old tap and the listener
+// loop creates a new one.
+#[cfg(target_os = "macos")]
+fn spawn_listener_watchdog(shared_state: *mut SharedState) {
+    unsafe extern "C" {
+        fn CFRunLoopGetMain() -> *mut std::ffi::c_void;
+        fn CFRunLoopStop(run_loop: *mut std::ffi::c_void);
     }
+
+    let watchdog_shared_state = shared_state as usize; // Convert to usize for thread safety
+    std::thread::spawn(move || {
+        let shared_ptr = watchdog_shared_state as *const SharedState;
+        let mut had_permission = unsafe { (*shared_ptr).has_accessibility_permission };
+        loop {
+            std::thread::sleep(Duration::from_millis(250));
+            let has_permission = unsafe { (*shared_ptr).has_accessibility_permission };
+            let listener_running =
+                input::LISTENER_RUNNING.load(std::sync::atomic::Ordering::SeqCst);
+            if has_permission && !had_permission && listener_running {
+                log::info!("Permission regained, restarting key listener");
+                unsafe {
+                    CFRunLoopStop(CFRunLoopGetMain());
+                }
+            }
+            had_permission = has_permission;
+        }
+    });
 }
 
 // Layout constants structure
@@ -366,70 +798,44 @@ struct KeyboardLayout {
     padding_x: f32,
     padding_y: f32,
     gap_multiplier: f32,
+    // Third row with the modifier keys (`[modifiers] show`)
+    modifier_row: bool,
+    // Space taken by the banner above the keyboard, if shown
+    vertical_offset: f32,
 }
 
 impl KeyboardLayout {
-    fn new() -> Self {
+    fn new(modifiers: &ModifierConfig, vertical_offset: f32) -> Self {
         Self {
             key_size: 60.0,
             key_spacing: 10.0,
             padding_x: 40.0,
             padding_y: 25.0,
             gap_multiplier: 5.5,
+            modifier_row: modifiers.show,
+            vertical_offset,
         }
     }
 
     fn right_start_x(&self) -> f32 {
         self.padding_x + (self.key_size + self.key_spacing) * self.gap_multiplier
     }
-}
 
-fn get_key_label(state: &SharedState, keycode: u16) -> &str {
-    match keycode {
-        0 => {
-            let bytes = &state.key_states.label_0;
-            std::str::from_utf8(&bytes[..bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len())])
-                .unwrap_or("A")
-        }
-        1 => {
-            let bytes = &state.key_states.label_1;
-            std::str::from_utf8(&bytes[..bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len())])
-                .unwrap_or("S")
-        }
-        2 => {
-            let bytes = &state.key_states.label_2;
-            std::str::from_utf8(&bytes[..bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len())])
-                .unwrap_or("D")
-        }
-        3 => {
-            let bytes = &state.key_states.label_3;
-            std::str::from_utf8(&bytes[..bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len())])
-                .unwrap_or("F")
-        }
-        38 => {
-            let bytes = &state.key_states.label_38;
-            std::str::from_utf8(&bytes[..bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len())])
-                .unwrap_or("J")
-        }
-        40 => {
-            let bytes = &state.key_states.label_40;
-            std::str::from_utf8(&bytes[..bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len())])
-                .unwrap_or("K")
-        }
-        37 => {
-            let bytes = &state.key_states.label_37;
-            std::str::from_utf8(&bytes[..bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len())])
-                .unwrap_or("L")
-        }
-        41 => {
-            let bytes = &state.key_states.label_41;
-            std::str::from_utf8(&bytes[..bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len())])
-                .unwrap_or(";")
-        }
-        53 => "ESC",
-        51 => "BACKSPACE",
-        49 => "SPACE",
-        _ => "?",
+    fn start_y(&self) -> f32 {
+        self.padding_y + self.vertical_offset
+    }
+
+    fn key_rows(&self) -> f32 {
+        if self.modifier_row { 3.0 } else { 2.0 }
+    }
+
+    fn keyboard_height(&self) -> f32 {
+        self.key_size * self.key_rows() + self.key_spacing * (self.key_rows() - 1.0)
+    }
+
+    // Text line in the bottom padding, below all key rows
+    fn bottom_line_y(&self) -> f32 {
+        self.start_y() + self.keyboard_height() + 7.0
     }
 }
 
@@ -438,50 +844,60 @@ fn draw_keyboard_layout(
     state: &SharedState,
     has_permission: bool,
     icons: &GameIcons,
-    vertical_offset: f32,
+    layout: &KeyboardLayout,
+    latency: Option<LatencySummary>,
+    options: KeyOptions,
 ) {
-    // Use layout constants structure
-    let layout = KeyboardLayout::new();
-    let start_y = layout.padding_y + vertical_offset;
+    let start_y = layout.start_y();
+    // One copy for the whole frame, so a layout switch shows up all at once
+    let mut layout_labels = state.layout.snapshot();
+    if state.input_method_active.load(Ordering::Relaxed) {
+        layout_labels.set_kind(LayoutKind::InputMethod);
+    }
+    let labels = KeyLabels {
+        layout: layout_labels,
+        main_layer: main_layer(options.labels, &state.key_states),
+        config: options.labels,
+        remap: options.remap,
+    };
+    let icons = KeyIcons {
+        icons,
+        theme: options.theme,
+    };
 
-    // Left side keys: ESC to the left of A, then A, S, D, F in a row (using dynamic labels)
-    // ESC should be at the left of A according to specification
+    // Left side: ESC to the left of A, then A, S, D, F in a row (QWERTY
+    // positions of the default bindings)
     let left_keys = [
         (
-            "ESC",
-            53,
+            Slot::Pause,
             layout.padding_x,
             start_y,
             layout.key_size,
             layout.key_size,
         ), // ESC at the left
         (
-            get_key_label(state, 0), // First key (A in QWERTY)
-            0,
+            Slot::Extra1,
             layout.padding_x + layout.key_size + layout.key_spacing,
             start_y,
             layout.key_size,
             layout.key_size,
         ),
         (
-            get_key_label(state, 1), // Second key (S in QWERTY)
-            1,
+            Slot::Retry,
             layout.padding_x + (layout.key_size + layout.key_spacing) * 2.0,
             start_y,
             layout.key_size,
             layout.key_size,
         ),
         (
-            get_key_label(state, 2), // Third key (D in QWERTY)
-            2,
+            Slot::Extra2,
             layout.padding_x + (layout.key_size + layout.key_spacing) * 3.0,
             start_y,
             layout.key_size,
             layout.key_size,
         ),
         (
-            get_key_label(state, 3), // Fourth key (F in QWERTY)
-            3,
+            Slot::Bomb,
             layout.padding_x + (layout.key_size + layout.key_spacing) * 4.0,
             start_y,
             layout.key_size,
@@ -491,44 +907,39 @@ fn draw_keyboard_layout(
     let backspace_x = layout.padding_x + layout.key_size + layout.key_spacing; // Start at A position
     let backspace_width = (layout.key_size + layout.key_spacing) * 4.0 - layout.key_spacing; // Span A through T
     let backspace = (
-        "BACKSPACE",
-        51,
+        Slot::Shot,
         backspace_x,
         start_y + layout.key_size + layout.key_spacing,
         backspace_width,
         layout.key_size,
     );
 
-    // Right side keys: J, K, L, ; (using dynamic labels)
+    // Right side: J, K, L, ; by default
     let right_start_x = layout.right_start_x(); // Calculate from layout
     let right_keys = [
         (
-            get_key_label(state, 38),
-            38,
+            Slot::Left,
             right_start_x,
             start_y,
             layout.key_size,
             layout.key_size,
-        ), // J in QWERTY
+        ),
         (
-            get_key_label(state, 40), // K in QWERTY
-            40,
+            Slot::Up,
             right_start_x + layout.key_size + layout.key_spacing,
             start_y,
             layout.key_size,
             layout.key_size,
         ),
         (
-            get_key_label(state, 37), // L in QWERTY
-            37,
+            Slot::Down,
             right_start_x + (layout.key_size + layout.key_spacing) * 2.0,
             start_y,
             layout.key_size,
             layout.key_size,
         ),
         (
-            get_key_label(state, 41), // ; in QWERTY
-            41,
+            Slot::Right,
             right_start_x + (layout.key_size + layout.key_spacing) * 3.0,
             start_y,
             layout.key_size,
@@ -539,70 +950,78 @@ fn draw_keyboard_layout(
     // SPACE - spanning the right side keys
     let space_width = (layout.key_size + layout.key_spacing) * 4.0 - layout.key_spacing;
     let space = (
-        "SPACE",
-        49,
+        Slot::Focus,
         right_start_x,
         start_y + layout.key_size + layout.key_spacing,
         space_width,
         layout.key_size,
     );
 
-    // Draw all keys
-    for &(label, keycode, x, y, width, height) in &left_keys {
-        draw_key(
-            d,
-            label,
-            keycode,
-            x,
-            y,
-            width,
-            height,
-            state,
-            has_permission,
-            icons,
-        );
-    }
+    // Modifier row: Shift, Control, Alt, Meta under A-F and under J-;. With
+    // merged sides only the left keys are drawn, lit by either side.
+    let modifier_y = start_y + (layout.key_size + layout.key_spacing) * 2.0;
+    let modifier_keys: Vec<_> = if layout.modifier_row {
+        let left = [
+            Slot::ShiftLeft,
+            Slot::ControlLeft,
+            Slot::AltLeft,
+            Slot::MetaLeft,
+        ];
+        let right = [
+            Slot::ShiftRight,
+            Slot::ControlRight,
+            Slot::AltRight,
+            Slot::MetaRight,
+        ];
+        let left_keys = left.iter().enumerate().map(|(column, &slot)| {
+            let x = backspace_x + (layout.key_size + layout.key_spacing) * column as f32;
+            (slot, x, modifier_y, layout.key_size, layout.key_size)
+        });
+        let right_keys = right.iter().enumerate().map(|(column, &slot)| {
+            let x = right_start_x + (layout.key_size + layout.key_spacing) * column as f32;
+            (slot, x, modifier_y, layout.key_size, layout.key_size)
+        });
+        if state.bindings.merge_sides {
+            left_keys.collect()
+        } else {
+            left_keys.chain(right_keys).collect()
+        }
+    } else {
+        Vec::new()
+    };
 
-    for &(label, keycode, x, y, width, height) in &right_keys {
-        draw_key(
-            d,
-            label,
-            keycode,
-            x,
-            y,
-            width,
-            height,
-            state,
-            has_permission,
-            icons,
-        );
-    }
+    // Remap mode adds a left Shift key under ESC, unless the modifier row
+    // already has one
+    let remap_shift = (options.remap.is_some() && !layout.modifier_row).then_some((
+        Slot::ShiftLeft,
+        layout.padding_x,
+        start_y + layout.key_size + layout.key_spacing,
+        layout.key_size,
+        layout.key_size,
+    ));
 
-    // Draw special keys
-    draw_key(
-        d,
-        backspace.0,
-        backspace.1,
-        backspace.2,
-        backspace.3,
-        backspace.4,
-        backspace.5,
+    // Draw all keys
+    let frame = KeyFrame {
         state,
-        has_permission,
+        labels: &labels,
         icons,
-    );
-    draw_key(
-        d,
-        space.0,
-        space.1,
-        space.2,
-        space.3,
-        space.4,
-        space.5,
-        state,
         has_permission,
-        icons,
-    );
+    };
+    let all_keys = left_keys
+        .iter()
+        .chain(&right_keys)
+        .chain([&backspace, &space])
+        .chain(&modifier_keys)
+        .chain(&remap_shift);
+    for &(slot, x, y, width, height) in all_keys {
+        draw_key(
+            d,
+            slot,
+            Rectangle::new(x, y, width, height),
+            frame,
+            options.learning == Some(slot),
+        );
+    }
 
     // Draw FPS indicator and Layout text on the same line
     // Ensure both are always visible by using a minimum Y position
@@ -622,38 +1041,151 @@ fn draw_keyboard_layout(
     );
 
     // Draw layout name at fixed position from left edge (independent of FPS width)
-    let layout_name = state.get_layout_name();
-    if !layout_name.is_empty() {
+    if !labels.layout.name().is_empty() {
+        let layout_text = format!("Layout: {}", labels.layout.name());
+        let layout_x = layout.padding_x + 80.0; // Fixed offset from left edge
         d.draw_text(
-            &format!("Layout: {}", layout_name),
-            (layout.padding_x + 80.0) as i32, // Fixed offset from left edge
+            &layout_text,
+            layout_x as i32,
             text_y as i32,
             12,
             Color::DARKGRAY,
         );
+
+        // Warning badge right after the name when the input source may not
+        // type what the keys show
+        if let Some(warning) = labels.layout.kind().warning() {
+            let badge_x = layout_x + d.measure_text(&layout_text, 12) as f32 + 6.0;
+            let badge_width = d.measure_text(warning, 10) as f32 + 8.0;
+            let badge_color = match labels.layout.kind() {
+                LayoutKind::InputMethod => Color::new(200, 50, 50, 255), // Red: keys may be swallowed
+                _ => Color::new(230, 140, 0, 255),                       // Orange
+            };
+            d.draw_rectangle_rounded(
+                Rectangle::new(badge_x, text_y - 1.0, badge_width, 14.0),
+                0.3,
+                4,
+                badge_color,
+            );
+            d.draw_text(
+                warning,
+                (badge_x + 4.0) as i32,
+                (text_y + 1.0) as i32,
+                10,
+                Color::WHITE,
+            );
+        }
     }
+
+    // Draw event-to-draw latency percentiles above the right side keys
+    if let Some(latency) = latency {
+        d.draw_text(
+            &format!("Latency {}", latency.format_millis()),
+            layout.right_start_x() as i32,
+            text_y as i32,
+            12,
+            Color::DARKGRAY,
+        );
+    }
+}
+
+// What changes how the keys are drawn, besides shared memory
+#[derive(Clone, Copy)]
+struct KeyOptions<'a> {
+    // `[labels]`
+    labels: &'a LabelConfig,
+    // Learn mode is waiting for this slot's key
+    learning: Option<Slot>,
+    // Remap mode is on
+    remap: Option<&'a RemapTable>,
+    // `[theme]`
+    theme: &'a ThemeConfig,
+}
+
+// What the keys show in one frame
+struct KeyLabels<'a> {
+    layout: LayoutLabels,
+    // Shift while a Shift key is held with `[labels] follow_shift`
+    main_layer: Layer,
+    config: &'a LabelConfig,
+    remap: Option<&'a RemapTable>,
+}
+
+// Icons and how to draw them
+#[derive(Clone, Copy)]
+struct KeyIcons<'a> {
+    icons: &'a GameIcons,
+    theme: &'a ThemeConfig,
 }
 
+// Everything the keys are drawn from in one frame
+#[derive(Clone, Copy)]
+struct KeyFrame<'a> {
+    state: &'a SharedState,
+    labels: &'a KeyLabels<'a>,
+    icons: KeyIcons<'a>,
+    has_permission: bool,
+}
+
+// How an icon is drawn in one key state
+struct IconStyle {
+    tint: Color,
+    size: f32,
+    position: IconPosition,
+}
+
+// Autorepeat typically fires every ~30-50ms, so this keeps the indicator lit
+// for as long as the key repeats
+const REPEAT_INDICATOR_NANOS: u64 = 150_000_000;
+
+// Render at the display's resolution, so icons are rasterized for it (see
+// GameIcons::rasterize). Only on macOS, where the window size and the mouse
+// position stay in points: the layout and the button hit-tests don't change,
+// only the framebuffer does. Elsewhere raylib 5.5 enlarges the window by the
+// monitor's scale instead, and a resize then mixes pixels into the window
+// coordinates, moving the keys and their hit areas apart.
+const HIGHDPI: bool = cfg!(target_os = "macos");
+
+// Default icon size, in window coordinates
+const ICON_SIZE: f32 = 16.0;
+// Next to the remapped key's name
+const REMAP_ICON_SIZE: f32 = 12.0;
+
 fn draw_key(
     d: &mut RaylibDrawHandle,
-    default_label: &str,
-    keycode: u32,
-    x: f32,
-    y: f32,
-    width: f32,
-    height: f32,
-    state: &SharedState,
-    has_permission: bool,
-    icons: &GameIcons,
+    slot: Slot,
+    key_rect: Rectangle,
+    frame: KeyFrame,
+    // Learn mode is waiting for this slot's key
+    highlighted: bool,
 ) {
-    let key_rect = Rectangle::new(x, y, width, height);
-    let is_pressed = state.key_states.get_key_state(keycode);
+    let Rectangle {
+        x,
+        y,
+        width,
+        height,
+    } = key_rect;
+    let KeyFrame {
+        state,
+        labels,
+        icons,
+        has_permission,
+    } = frame;
+    let key = state.bindings.key(slot);
+    let is_pressed = state.bindings.is_pressed(slot, &state.key_states);
 
-    // Check if this key is unused (A or D keys don't have functions)
-    let is_unused_key = keycode == 0 || keycode == 2;
+    // The extra slots (A and D by default) don't have functions
+    let is_unused_key = slot == Slot::Extra1 || slot == Slot::Extra2;
 
     // Determine key colors based on state
-    let (bg_color, border_color, text_color) = if !has_permission {
+    let (bg_color, border_color, text_color) = if highlighted {
+        // Learn mode prompt, same orange as the banners
+        (
+            Color::new(255, 225, 170, 255),
+            Color::new(255, 165, 0, 255),
+            Color::BLACK,
+        )
+    } else if !has_permission {
         // Red when permissions missing
         (
             Color::new(255, 200, 200, 255),
@@ -689,88 +1221,250 @@ fn draw_key(
     // Draw key border
     d.draw_rectangle_rounded_lines(key_rect, 0.1, 10, border_color);
 
-    // Get the main label for this key (from layout or default)
-    let main_label = get_key_main_label(default_label, keycode, state);
+    let icon_style = get_icon_style(icons.theme, has_permission, is_pressed);
+    // `[theme] key_style = "icon-only"`: keys with an icon show just that
+    let icon_only = icons.theme.key_style == KeyStyle::IconOnly
+        && icons
+            .icons
+            .get_icon_texture(slot, icons.theme.icon_only_size)
+            .is_some();
+
+    // Get the main label for this key (`[labels] main`, or from layout)
+    let merge_sides = state.bindings.merge_sides;
+    let main_label = match labels.config.main {
+        _ if icon_only => String::new(),
+        LabelSource::None => String::new(),
+        source => get_key_label(source, slot, key, labels, merge_sides)
+            .unwrap_or_else(|| get_layout_label(key, labels, merge_sides)),
+    };
 
-    // Draw main label (center, prominent)
-    let text_size = if width > 100.0 { 16 } else { 20 };
-    let text_width = d.measure_text(&main_label, text_size);
+    // Draw main label (center, prominent), shrunk until long labels such as
+    // "L SHIFT" fit
+    let mut text_size = if width > 100.0 { 16 } else { 20 };
+    let mut text_width = d.measure_text(&main_label, text_size);
+    while text_size > 10 && text_width as f32 > width - 8.0 {
+        text_size -= 2;
+        text_width = d.measure_text(&main_label, text_size);
+    }
     let text_x = (x + width / 2.0 - text_width as f32 / 2.0) as i32;
     let text_y = (y + height / 2.0 - text_size as f32 / 2.0) as i32;
     d.draw_text(&main_label, text_x, text_y, text_size, text_color);
 
-    // Draw QWERTY hint (top-left, blue, small) - shows QWERTY position for non-special keys
-    let qwerty_hint = get_qwerty_hint(keycode);
-    if !qwerty_hint.is_empty() && main_label.to_uppercase() != qwerty_hint.to_uppercase() {
-        d.draw_text(
-            &qwerty_hint,
-            (x + 3.0) as i32,
-            (y + 3.0) as i32,
-            8,
-            Color::BLUE,
-        );
+    // Draw hint (top-left, blue, small) - `[labels] hint`, QWERTY position by
+    // default
+    let hint =
+        get_key_label(labels.config.hint, slot, key, labels, merge_sides).unwrap_or_default();
+    if !icon_only && !hint.is_empty() && main_label.to_uppercase() != hint.to_uppercase() {
+        d.draw_text(&hint, (x + 3.0) as i32, (y + 3.0) as i32, 8, Color::BLUE);
     }
 
-    // Draw functional icon (bottom) for gaming context
-    if let Some(icon_texture) = icons.get_icon_texture(keycode) {
-        // Icon size (small, bottom of key)
-        let icon_size = 16.0;
-        let icon_x = x + width / 2.0 - icon_size / 2.0;
-        let icon_y = y + height - icon_size - 2.0;
+    // Shift (top-right) and AltGr (bottom-right) legends, unless they only
+    // repeat the main label (e.g. "A" on the A key)
+    if labels.config.legends && !icon_only {
+        for (layer, legend_y) in [(Layer::Shift, y + 3.0), (Layer::AltGr, y + height - 11.0)] {
+            let legend = labels.layout.label(key, layer);
+            if legend.is_empty() || legend.to_uppercase() == main_label.to_uppercase() {
+                continue;
+            }
+            let legend_width = d.measure_text(legend, 8);
+            d.draw_text(
+                legend,
+                (x + width - 3.0) as i32 - legend_width,
+                legend_y as i32,
+                8,
+                Color::GRAY,
+            );
+        }
+    }
 
-        // Draw the icon with appropriate tint based on key state
-        let icon_tint = if has_permission {
-            Color::new(100, 100, 100, 200) // Semi-transparent dark gray
+    // Repeat indicator (bottom-left) while the key autorepeats; only ever set
+    // with `[input] show_repeats`
+    if is_pressed && state.key_states.is_repeating(key, REPEAT_INDICATOR_NANOS) {
+        d.draw_circle(
+            (x + 8.0) as i32,
+            (y + height - 8.0) as i32,
+            3.0,
+            Color::new(100, 150, 200, 255),
+        );
+    }
+
+    // Remap mode: what the key is remapped to, at the bottom with the icon
+    let remapped = labels
+        .remap
+        .and_then(|remap| remap.label(key))
+        .filter(|&remapped| !remap::icon_shows(slot, remapped));
+    if let Some(remapped) = remapped.filter(|_| !icon_only) {
+        let icon_size = REMAP_ICON_SIZE;
+        let icon = icons.icons.get_icon_texture(slot, icon_size);
+        // "<icon> = R", or "-> R" for keys without an icon
+        let text = match icon {
+            Some(_) => format!("= {}", remapped),
+            None => format!("-> {}", remapped),
+        };
+        let icon_width = if icon.is_some() { icon_size + 3.0 } else { 0.0 };
+        let text_width = d.measure_text(&text, 10) as f32;
+        let start_x = x + width / 2.0 - (icon_width + text_width) / 2.0;
+        let bottom_y = y + height - icon_size - 2.0;
+
+        if let Some(icon_texture) = icon {
+            d.draw_texture_ex(
+                icon_texture,
+                Vector2::new(start_x, bottom_y),
+                0.0,
+                icon_size / icon_texture.width as f32,
+                icon_style.tint,
+            );
+        }
+        d.draw_text(
+            &text,
+            (start_x + icon_width) as i32,
+            (bottom_y + 1.0) as i32,
+            10,
+            text_color,
+        );
+    } else {
+        // Icon-only keys center a large icon
+        let (icon_size, position) = if icon_only {
+            (icons.theme.icon_only_size, IconPosition::Center)
         } else {
-            Color::new(150, 50, 50, 150) // Semi-transparent dark red when no permission
+            (icon_style.size, icon_style.position)
         };
+        if let Some(icon_texture) = icons.icons.get_icon_texture(slot, icon_size) {
+            let icon_x = x + width / 2.0 - icon_size / 2.0;
+            let icon_y = match position {
+                IconPosition::Top => y + 2.0,
+                IconPosition::Center => y + height / 2.0 - icon_size / 2.0,
+                IconPosition::Bottom => y + height - icon_size - 2.0,
+            };
+
+            d.draw_texture_ex(
+                icon_texture,
+                Vector2::new(icon_x, icon_y),
+                0.0,                                   // rotation
+                icon_size / icon_texture.width as f32, // scale to fit icon_size
+                icon_style.tint,
+            );
+        }
+    }
 
-        d.draw_texture_ex(
-            icon_texture,
-            Vector2::new(icon_x, icon_y),
-            0.0,                                   // rotation
-            icon_size / icon_texture.width as f32, // scale to fit icon_size
-            icon_tint,
+    // Icon-only keys keep their large icon and show the remap in the
+    // top-right corner, where the Shift legend would be
+    if icon_only && let Some(remapped) = remapped {
+        let text = format!("-> {}", remapped);
+        let text_width = d.measure_text(&text, 10);
+        d.draw_text(
+            &text,
+            (x + width - 3.0) as i32 - text_width,
+            (y + 3.0) as i32,
+            10,
+            text_color,
         );
     }
 }
 
-fn get_key_main_label(default_label: &str, keycode: u32, state: &SharedState) -> String {
-    // Get label from current keyboard layout stored in shared state
-    let layout_label = match keycode {
-        0 => state.get_key_label("a"),  // keycode 0 = A key
-        1 => state.get_key_label("s"),  // keycode 1 = S key
-        2 => state.get_key_label("d"),  // keycode 2 = D key
-        3 => state.get_key_label("f"),  // keycode 3 = F key
-        38 => state.get_key_label("j"), // keycode 38 = J key
-        40 => state.get_key_label("k"), // keycode 40 = K key
-        37 => state.get_key_label("l"), // keycode 37 = L key
-        41 => state.get_key_label(";"), // keycode 41 = ; key
-        _ => String::new(),
+// `[theme.icon]` for the key's state, over the built-in defaults
+fn get_icon_style(theme: &ThemeConfig, has_permission: bool, is_pressed: bool) -> IconStyle {
+    let (config, default_tint) = if !has_permission {
+        // Semi-transparent dark red when no permission
+        (&theme.icon.disabled, Color::new(150, 50, 50, 150))
+    } else if is_pressed {
+        // Darker, like the pressed key's border
+        (&theme.icon.pressed, Color::new(50, 80, 120, 230))
+    } else {
+        // Semi-transparent dark gray
+        (&theme.icon.normal, Color::new(100, 100, 100, 200))
     };
 
-    if !layout_label.is_empty() && layout_label != "?" {
-        layout_label.to_uppercase()
+    IconStyle {
+        tint: config
+            .tint
+            .map_or(default_tint, |[r, g, b, a]| Color::new(r, g, b, a)),
+        size: config.size.unwrap_or(ICON_SIZE),
+        position: config.position.unwrap_or(IconPosition::Bottom),
+    }
+}
+
+// Text for `source`, if the key has any
+fn get_key_label(
+    source: LabelSource,
+    slot: Slot,
+    key: PhysicalKey,
+    labels: &KeyLabels,
+    merge_sides: bool,
+) -> Option<String> {
+    match source {
+        LabelSource::Layout => Some(get_layout_label(key, labels, merge_sides)),
+        LabelSource::Qwerty => Some(get_qwerty_label(key, merge_sides)),
+        LabelSource::Action => slot.action().map(str::to_string),
+        LabelSource::Custom => labels.config.custom.get(slot.name()).cloned(),
+        LabelSource::None => None,
+    }
+}
+
+// The layer main labels come from: Shift while a Shift key is held with
+// `[labels] follow_shift`, the base layer otherwise
+fn main_layer(config: &LabelConfig, key_states: &KeyStates) -> Layer {
+    let shift_held = key_states.get_key_state(PhysicalKey::ShiftLeft)
+        || key_states.get_key_state(PhysicalKey::ShiftRight);
+    if config.follow_shift && shift_held {
+        Layer::Shift
     } else {
-        default_label.to_string()
+        Layer::Base
     }
 }
 
-fn get_qwerty_hint(keycode: u32) -> &'static str {
-    // Returns the QWERTY character for the physical key position
-    match keycode {
-        0 => "A",  // A position
-        1 => "S",  // S position in QWERTY
-        2 => "D",  // D position in QWERTY
-        3 => "F",  // F position in QWERTY
-        38 => "J", // J position in QWERTY
-        40 => "K", // K position in QWERTY
-        37 => "L", // L position in QWERTY
-        41 => ";", // ; position in QWERTY
-        _ => "",   // No hint for special keys
+fn get_layout_label(key: PhysicalKey, labels: &KeyLabels, merge_sides: bool) -> String {
+    // Label from the current keyboard layout, falling back to the base layer
+    // for keys with nothing on the Shift layer; keys that don't type a
+    // character have none
+    let layout_label = [labels.main_layer, Layer::Base]
+        .into_iter()
+        .map(|layer| labels.layout.label(key, layer))
+        .find(|label| !label.is_empty())
+        .unwrap_or_default();
+
+    if !layout_label.is_empty() {
+        layout_label.to_uppercase()
+    } else if let Some(label) = get_modifier_label(key, merge_sides) {
+        label
+    } else {
+        // "ESC", "BACKSPACE", "SPACE", ...
+        key.name().to_uppercase()
     }
 }
 
+// "L SHIFT", "R CMD", ...; no side when both sides count as one key
+fn get_modifier_label(key: PhysicalKey, merge_sides: bool) -> Option<String> {
+    let (alt, meta) = if cfg!(target_os = "macos") {
+        ("OPT", "CMD")
+    } else {
+        ("ALT", "SUPER")
+    };
+    let (side, name) = match key {
+        PhysicalKey::ShiftLeft => ("L", "SHIFT"),
+        PhysicalKey::ShiftRight => ("R", "SHIFT"),
+        PhysicalKey::ControlLeft => ("L", "CTRL"),
+        PhysicalKey::ControlRight => ("R", "CTRL"),
+        PhysicalKey::AltLeft => ("L", alt),
+        PhysicalKey::AltRight => ("R", alt),
+        PhysicalKey::MetaLeft => ("L", meta),
+        PhysicalKey::MetaRight => ("R", meta),
+        _ => return None,
+    };
+
+    Some(if merge_sides {
+        name.to_string()
+    } else {
+        format!("{} {}", side, name)
+    })
+}
+
+fn get_qwerty_label(key: PhysicalKey, merge_sides: bool) -> String {
+    // The QWERTY character for the physical key position, or the key's name
+    // for special keys
+    get_modifier_label(key, merge_sides).unwrap_or_else(|| key.name().to_uppercase())
+}
+
 // Layout calculation structure
 struct LayoutDimensions {
     window_width: i32,
@@ -824,10 +1518,76 @@ impl BannerLayout {
     }
 }
 
+// "Remap", "Inspect" and "Learn" text buttons on the bottom line,
+// right-aligned with the space key, each as wide as its text
+struct InspectorButtons {
+    remap: TextButton,
+    inspect: TextButton,
+    learn: TextButton,
+}
+
+#[derive(Clone, Copy)]
+struct TextButton {
+    label: &'static str,
+    rect: Rectangle,
+}
+
+impl InspectorButtons {
+    const FONT_SIZE: i32 = 10;
+    const BUTTON_HEIGHT: f32 = 14.0;
+    const BUTTON_SPACING: f32 = 10.0;
+
+    fn new(
+        rl: &RaylibHandle,
+        window_width: f32,
+        layout: &KeyboardLayout,
+        inspector: &KeyInspector,
+        remap_enabled: bool,
+    ) -> Self {
+        let remap_label = if remap_enabled { "No remap" } else { "Remap" };
+        let inspect_label = if inspector.visible { "Hide" } else { "Inspect" };
+        let learn_label = if inspector.learning_slot().is_some() {
+            "Cancel"
+        } else {
+            "Learn"
+        };
+
+        // Laid out from the right
+        let y = layout.bottom_line_y() - 2.0;
+        let mut right = window_width - layout.padding_x;
+        let mut button = |label| {
+            let width = rl.measure_text(label, Self::FONT_SIZE) as f32;
+            right -= width;
+            let rect = Rectangle::new(right, y, width, Self::BUTTON_HEIGHT);
+            right -= Self::BUTTON_SPACING;
+            TextButton { label, rect }
+        };
+        let learn = button(learn_label);
+        let inspect = button(inspect_label);
+        let remap = button(remap_label);
+
+        Self {
+            remap,
+            inspect,
+            learn,
+        }
+    }
+
+    fn all(&self) -> [TextButton; 3] {
+        [self.remap, self.inspect, self.learn]
+    }
+
+    fn is_hovered(&self, mouse_pos: Vector2) -> bool {
+        self.all()
+            .iter()
+            .any(|button| button.rect.check_collision_point_rec(mouse_pos))
+    }
+}
+
 impl LayoutDimensions {
-    fn calculate() -> Self {
+    fn calculate(modifiers: &ModifierConfig) -> Self {
         // Use the same layout constants as KeyboardLayout
-        let keyboard_layout = KeyboardLayout::new();
+        let keyboard_layout = KeyboardLayout::new(modifiers, 0.0);
 
         // Calculate keyboard dimensions
         // Right side: 4 keys (N,E,I,O)
@@ -843,8 +1603,8 @@ impl LayoutDimensions {
         let window_width = (keyboard_right_edge + keyboard_layout.padding_x) as i32;
 
         // Calculate height
-        // 2 rows of keys + backspace row + space row
-        let keyboard_height = keyboard_layout.key_size * 2.0 + keyboard_layout.key_spacing * 1.0; // 2 key rows + spacing between them
+        // 2 rows of keys (plus the modifier row) and the spacing between them
+        let keyboard_height = keyboard_layout.keyboard_height();
         let base_height = (keyboard_height + keyboard_layout.padding_y * 2.0) as i32;
 
         // Banner height for permission warning
@@ -859,16 +1619,29 @@ impl LayoutDimensions {
     }
 }
 
-fn run_ui_process(shared_state: *mut SharedState) {
+// Returns the exit code for the UI process
+fn run_ui_process(
+    shared_state: *mut SharedState,
+    config: &Config,
+    wakeup: Option<WakeupReceiver>,
+    supervisor_pid: i32,
+) -> i32 {
     // Calculate layout dimensions dynamically
-    let layout = LayoutDimensions::calculate();
+    let layout = LayoutDimensions::calculate(&config.modifiers);
 
-    let (mut rl, thread) = raylib::init()
+    let mut builder = raylib::init();
+    builder
         .size(layout.window_width, layout.window_height)
         .title("THKeyVis")
         .transparent()
-        .resizable()
-        .build();
+        .resizable();
+    if HIGHDPI {
+        builder.highdpi();
+    }
+    if config.frame.pacing == PacingMode::Vsync {
+        builder.vsync();
+    }
+    let (mut rl, thread) = builder.build();
 
     // Setup window management (always-on-top, dragging, custom title) via Swift
     unsafe {
@@ -877,22 +1650,55 @@ fn run_ui_process(shared_state: *mut SharedState) {
 
     // Load game icons
     let mut icons = GameIcons::new();
-    icons.load_icons(&mut rl, &thread);
-
-    let mut last_permission_state = false;
-
-    // Get monitor refresh rate and set target FPS to twice that
-    let current_monitor = raylib::core::window::get_current_monitor();
-    let monitor_refresh_rate = raylib::core::window::get_monitor_refresh_rate(current_monitor);
-    let target_fps = monitor_refresh_rate * 2; // Target twice the refresh rate
-    let frame_duration_nanos = 1_000_000_000 / target_fps as u64; // nanoseconds per frame
-    let mut start_time = Instant::now();
-    let mut frame_count = 0u64;
+    let failed_icons = icons.load_icons(&mut rl, &thread, &config.icons);
+    if !failed_icons.is_empty() {
+        log::error!(icons:? = failed_icons; "Failed to load icons");
+        unsafe {
+            (*shared_state).status.report(
+                StatusCode::IconLoadFailed,
+                &format!("Failed to load icons: {}", failed_icons.join(", ")),
+            );
+        }
+    }
 
-    println!(
-        "Monitor refresh rate: {}Hz, Target FPS: {}",
-        monitor_refresh_rate, target_fps
+    // Every size an icon can be drawn at, for SVG icons
+    let icon_sizes: Vec<f32> = [(true, false), (true, true), (false, false)]
+        .into_iter()
+        .map(|(has_permission, is_pressed)| {
+            get_icon_style(&config.theme, has_permission, is_pressed).size
+        })
+        .chain([config.theme.icon_only_size, REMAP_ICON_SIZE])
+        .collect();
+
+    let mut last_banner_state = true;
+    let mut latency_tracker = LatencyTracker::new();
+    let mut monitor_health = MonitorHealth::new();
+    let mut inspector = KeyInspector::new(unsafe { &*shared_state }, config.profile.clone());
+    let remap_table = RemapTable::from_config(&config.remap);
+    let mut remap_enabled = config.remap.enabled;
+    for name in config.labels.custom.keys() {
+        if Slot::from_name(name).is_none() {
+            log::warn!(slot = name.as_str(); "Unknown slot in [labels.custom], ignoring it");
+        }
+    }
+    let mut layout_toast = LayoutToast::new(
+        unsafe { &*shared_state },
+        Duration::from_millis(config.layout.toast_ms),
     );
+    // Only used for banner text and the settings button; the monitor process
+    // does the checking
+    let permission_provider = permission::platform_provider();
+
+    // Get monitor refresh rate and derive the target FPS from the frame policy
+    let frame_policy = FrameRatePolicy::from_config(&config.frame);
+    let mut monitor_watcher = MonitorWatcher::new();
+    let mut pacer = FramePacer::new(
+        config.frame.pacing,
+        frame_policy.target_fps(monitor_watcher.refresh_rate),
+        wakeup,
+        std::time::Duration::from_millis(config.frame.idle_redraw_ms),
+    );
+    log_frame_rate(&frame_policy, &monitor_watcher, &pacer);
 
     while !rl.window_should_close() {
         // Read shared state once per frame to check for close request
@@ -903,6 +1709,23 @@ fn run_ui_process(shared_state: *mut SharedState) {
             break;
         }
 
+        // Don't outlive the monitor process: nothing would update the keys
+        if !supervisor::parent_is_alive(supervisor_pid) {
+            log::warn!(supervisor_pid = supervisor_pid; "Supervisor process is gone, exiting");
+            return EXIT_PARENT_GONE;
+        }
+
+        // Remember which key event this frame is going to show
+        let (event_seq, event_nanos) = state.input_event();
+        latency_tracker.observe_state(event_seq, event_nanos);
+
+        // Report a monitor process that is alive but no longer updating state
+        monitor_health.check(shared_state);
+
+        // Feed new key presses to learn mode
+        inspector.update(shared_state);
+        layout_toast.update(state, Instant::now());
+
         // Get input state before drawing
         let mouse_pos = rl.get_mouse_position();
         let mouse_clicked = rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT);
@@ -910,36 +1733,87 @@ fn run_ui_process(shared_state: *mut SharedState) {
         // Check accessibility permission state from shared memory
         let has_permission = state.has_accessibility_permission;
 
-        // Dynamically resize window based on permission state
-        if has_permission != last_permission_state {
-            let target_height = if has_permission {
-                layout.base_height
-            } else {
+        // Errors reported by either process; the permission banner wins since
+        // most errors are expected while permission is missing
+        let status = state.status.most_severe();
+        let show_banner = !has_permission || status.is_some();
+
+        // Dynamically resize window based on whether a banner is shown
+        if show_banner != last_banner_state {
+            let target_height = if show_banner {
                 layout.window_height
+            } else {
+                layout.base_height
             };
             rl.set_window_size(layout.window_width, target_height);
-            last_permission_state = has_permission;
+            last_banner_state = show_banner;
         }
 
         // Get window dimensions before drawing
         let window_width = rl.get_screen_width() as f32;
         let window_height = rl.get_screen_height();
 
+        // Calculate vertical offset based on whether banner is shown
+        let keyboard_offset_y = if show_banner {
+            layout.banner_height as f32
+        } else {
+            0.0
+        };
+
         // Calculate button area if permission banner is shown (for cursor change)
         let banner_layout = BannerLayout::new();
-        let is_button_hovered = if !has_permission {
+        let is_button_hovered = if !has_permission && permission_provider.can_open_settings() {
             banner_layout.is_button_hovered(window_width, mouse_pos.x, mouse_pos.y)
         } else {
             false
         };
 
+        let keyboard_layout = KeyboardLayout::new(&config.modifiers, keyboard_offset_y);
+        let inspector_buttons = InspectorButtons::new(
+            &rl,
+            window_width,
+            &keyboard_layout,
+            &inspector,
+            remap_enabled,
+        );
+        let is_inspector_hovered = inspector_buttons.is_hovered(mouse_pos);
+        if mouse_clicked {
+            if inspector_buttons
+                .remap
+                .rect
+                .check_collision_point_rec(mouse_pos)
+            {
+                remap_enabled = !remap_enabled;
+                log::info!(enabled = remap_enabled; "Remap mode toggled");
+            } else if inspector_buttons
+                .inspect
+                .rect
+                .check_collision_point_rec(mouse_pos)
+            {
+                inspector.visible = !inspector.visible;
+            } else if inspector_buttons
+                .learn
+                .rect
+                .check_collision_point_rec(mouse_pos)
+            {
+                inspector.toggle_learning(state);
+            }
+        }
+
         // Set cursor based on hover state (with Swift-side cleanup to prevent crashes)
-        if is_button_hovered {
+        if is_button_hovered || is_inspector_hovered {
             rl.set_mouse_cursor(MouseCursor::MOUSE_CURSOR_POINTING_HAND);
         } else {
             rl.set_mouse_cursor(MouseCursor::MOUSE_CURSOR_DEFAULT);
         }
 
+        let dpi_scale = if HIGHDPI {
+            rl.get_window_scale_dpi().x
+        } else {
+            1.0
+        };
+        icons.rasterize(&mut rl, &thread, dpi_scale, &icon_sizes);
+
         let mut d = rl.begin_drawing(&thread);
 
         // Clear with transparent background
@@ -977,188 +1851,302 @@ fn run_ui_process(shared_state: *mut SharedState) {
                 Color::new(255, 165, 0, 255),
             ); // Orange
 
-            // Text: "Input Monitoring permission required" - positioned relative to banner
+            // Title and platform-specific guidance - positioned relative to banner
             d.draw_text(
-                "Input Monitoring permission required",
+                &permission_provider.banner_title(state.permission),
                 (banner_x + 35.0) as i32,
-                (banner_y + 18.0) as i32,
+                (banner_y + 11.0) as i32,
                 12,
                 Color::new(255, 165, 0, 255), // Orange text
             );
-
-            // "Open Settings" button area (right side of banner) - use consistent calculations
-            let button_x = banner_layout.button_x(window_width);
-            let button_y = banner_layout.button_y();
-
-            // Check if mouse is over button (use same calculation as cursor logic)
-            let is_button_hovered =
-                banner_layout.is_button_hovered(window_width, mouse_pos.x, mouse_pos.y);
-
-            // Button text
-            let button_color = if is_button_hovered {
-                Color::new(0, 0, 139, 255) // Darker blue when hovered
-            } else {
-                Color::new(0, 122, 255, 255) // Blue (matching SwiftUI .blue)
-            };
-
             d.draw_text(
-                "Open Settings",
-                (button_x + 5.0) as i32,
-                (button_y + 6.0) as i32,
-                11,
-                button_color,
+                permission_provider.guidance(),
+                (banner_x + 35.0) as i32,
+                (banner_y + 29.0) as i32,
+                10,
+                Color::new(255, 165, 0, 200),
             );
 
-            // Handle button click
-            if is_button_hovered && mouse_clicked {
-                // Open System Preferences directly from Rust
-                let _ = Command::new("open")
-                    .arg("x-apple.systempreferences:com.apple.preference.security?Privacy_ListenEvent")
-                    .spawn();
+            if permission_provider.can_open_settings() {
+                // "Open Settings" button area (right side of banner) - use consistent calculations
+                let button_x = banner_layout.button_x(window_width);
+                let button_y = banner_layout.button_y();
+
+                // Button text
+                let button_color = if is_button_hovered {
+                    Color::new(0, 0, 139, 255) // Darker blue when hovered
+                } else {
+                    Color::new(0, 122, 255, 255) // Blue (matching SwiftUI .blue)
+                };
+
+                d.draw_text(
+                    "Open Settings",
+                    (button_x + 5.0) as i32,
+                    (button_y + 6.0) as i32,
+                    11,
+                    button_color,
+                );
+
+                // Handle button click
+                if is_button_hovered && mouse_clicked {
+                    permission_provider.open_settings();
+                }
             }
+        } else if let Some(status) = &status {
+            draw_status_banner(&mut d, &banner_layout, window_width, status);
         }
 
         // Draw keyboard layout according to SPECIFICATION.md
-        // Calculate vertical offset based on whether banner is shown
-        let keyboard_offset_y = if has_permission {
-            0.0
-        } else {
-            layout.banner_height as f32
-        };
-        draw_keyboard_layout(&mut d, state, has_permission, &icons, keyboard_offset_y);
-
-        // Aggressive frame timing with enhanced compensation for missed frames
-        frame_count += 1;
-        let target_frame_time =
-            start_time + std::time::Duration::from_nanos(frame_count * frame_duration_nanos);
-        let now = Instant::now();
-
-        // Check if we missed frames (frame took too long)
-        if now > target_frame_time {
-            // Calculate how many frames we're behind
-            let elapsed_since_start = now.duration_since(start_time).as_nanos() as u64;
-            let actual_frame_count = elapsed_since_start / frame_duration_nanos;
-
-            // More aggressive compensation - skip ahead further to account for system load
-            let compensation_buffer = 2; // Add extra frames to compensate for system spikes
-            if actual_frame_count > frame_count {
-                frame_count = actual_frame_count + compensation_buffer;
-            }
+        draw_keyboard_layout(
+            &mut d,
+            state,
+            has_permission,
+            &icons,
+            &keyboard_layout,
+            latency_tracker.summary(),
+            KeyOptions {
+                labels: &config.labels,
+                learning: inspector.learning_slot(),
+                remap: remap_enabled.then_some(&remap_table),
+                theme: &config.theme,
+            },
+        );
+        if let Some((text, opacity)) = layout_toast.text(Instant::now()) {
+            draw_layout_toast(&mut d, text, opacity, window_width, &keyboard_layout);
+        }
+        draw_inspector(
+            &mut d,
+            &inspector,
+            &inspector_buttons,
+            state,
+            &keyboard_layout,
+            mouse_pos,
+        );
 
-            // If we're severely behind (more than 5 frames), reset timing to current time
-            let frames_behind =
-                actual_frame_count.saturating_sub(frame_count.saturating_sub(compensation_buffer));
-            if frames_behind > 5 {
-                // Reset timing base to prevent perpetual lag
-                start_time = now;
-                frame_count = 0;
-            }
-        } else {
-            // We're on time or early, sleep until next frame slot
-            let sleep_duration = target_frame_time - now;
-            // Only sleep if the duration is meaningful (avoid micro-sleeps)
-            if sleep_duration > std::time::Duration::from_micros(100) {
-                std::thread::sleep(sleep_duration);
-            }
+        // End the frame here (instead of at the end of the loop body) so the
+        // latency sample is taken right after the buffer swap
+        drop(d);
+        latency_tracker.frame_presented(monotonic_nanos());
+
+        if let Some(report) = latency_tracker.take_report() {
+            log::info!(
+                p50_us = report.p50_micros,
+                p95_us = report.p95_micros,
+                p99_us = report.p99_micros,
+                samples = report.sample_count;
+                "Input latency"
+            );
+        }
+
+        // Re-detect the refresh rate when the window moves to another monitor
+        if monitor_watcher.poll() {
+            pacer.set_target_fps(frame_policy.target_fps(monitor_watcher.refresh_rate));
+            log_frame_rate(&frame_policy, &monitor_watcher, &pacer);
         }
+
+        pacer.wait_for_next_frame();
     }
+
+    EXIT_OK
 }
 
-// Embedded icon data using include_bytes!
-const ARROW_LEFT_PNG: &[u8] = include_bytes!("../../icons/MaterialSymbolsArrowBack.png");
-const ARROW_RIGHT_PNG: &[u8] = include_bytes!("../../icons/MaterialSymbolsArrowForward.png");
-const ARROW_UP_PNG: &[u8] = include_bytes!("../../icons/MaterialSymbolsArrowUpward.png");
-const ARROW_DOWN_PNG: &[u8] = include_bytes!("../../icons/MaterialSymbolsArrowDownward.png");
-const BOMB_PNG: &[u8] = include_bytes!("../../icons/MaterialSymbolsBomb.png");
-const FOCUS_PNG: &[u8] = include_bytes!("../../icons/MaterialSymbolsFilterCenterFocus.png");
-const REFRESH_PNG: &[u8] = include_bytes!("../../icons/MaterialSymbolsRefresh.png");
-const SHOOT_PNG: &[u8] = include_bytes!("../../icons/EosIconsTroubleshooting.png");
+// Error/warning banner, same geometry as the permission banner but without a
+// button
+fn draw_status_banner(
+    d: &mut RaylibDrawHandle,
+    banner_layout: &BannerLayout,
+    window_width: f32,
+    status: &StatusEntry,
+) {
+    let banner_x = banner_layout.banner_x(window_width);
+    let banner_y = banner_layout.banner_y;
+    let banner_rect = Rectangle::new(
+        banner_x,
+        banner_y,
+        banner_layout.banner_width,
+        banner_layout.banner_height,
+    );
 
-// Icon storage for gaming icons
-struct GameIcons {
-    arrow_left: Option<Texture2D>,
-    arrow_right: Option<Texture2D>,
-    arrow_up: Option<Texture2D>,
-    arrow_down: Option<Texture2D>,
-    bomb: Option<Texture2D>,
-    focus: Option<Texture2D>,
-    refresh: Option<Texture2D>,
-    shoot: Option<Texture2D>,
+    let (r, g, b) = match status.code.severity() {
+        Severity::Error => (255, 80, 80),   // Red
+        Severity::Warning => (255, 165, 0), // Orange
+    };
+
+    d.draw_rectangle_rounded(banner_rect, 0.16, 20, Color::new(r, g, b, 25));
+    d.draw_rectangle_rounded_lines(banner_rect, 0.16, 20, Color::new(r, g, b, 76));
+    d.draw_text(
+        "⚠",
+        (banner_x + 12.0) as i32,
+        (banner_y + 15.0) as i32,
+        16,
+        Color::new(r, g, b, 255),
+    );
+    d.draw_text(
+        &status.message,
+        (banner_x + 35.0) as i32,
+        (banner_y + 18.0) as i32,
+        12,
+        Color::new(r, g, b, 255),
+    );
 }
 
-impl GameIcons {
+// Centered over the keys
+fn draw_layout_toast(
+    d: &mut RaylibDrawHandle,
+    text: &str,
+    opacity: f32,
+    window_width: f32,
+    layout: &KeyboardLayout,
+) {
+    let text_width = d.measure_text(text, 16) as f32;
+    let width = text_width + 24.0;
+    let height = 30.0;
+    let x = (window_width - width) / 2.0;
+    let y = layout.start_y() + (layout.keyboard_height() - height) / 2.0;
+
+    d.draw_rectangle_rounded(
+        Rectangle::new(x, y, width, height),
+        0.3,
+        6,
+        Color::new(0, 0, 0, (200.0 * opacity) as u8),
+    );
+    d.draw_text(
+        text,
+        (x + 12.0) as i32,
+        (y + 7.0) as i32,
+        16,
+        Color::new(255, 255, 255, (255.0 * opacity) as u8),
+    );
+}
+
+// Remap/inspector/learn buttons and the line of text next to them
+fn draw_inspector(
+    d: &mut RaylibDrawHandle,
+    inspector: &KeyInspector,
+    buttons: &InspectorButtons,
+    state: &SharedState,
+    layout: &KeyboardLayout,
+    mouse_pos: Vector2,
+) {
+    let text_y = layout.bottom_line_y() as i32;
+
+    for button in buttons.all() {
+        let color = if button.rect.check_collision_point_rec(mouse_pos) {
+            Color::new(0, 0, 139, 255) // Darker blue when hovered
+        } else {
+            Color::new(0, 122, 255, 255) // Blue, like "Open Settings"
+        };
+        d.draw_text(
+            button.label,
+            button.rect.x as i32,
+            text_y,
+            InspectorButtons::FONT_SIZE,
+            color,
+        );
+    }
+
+    if let Some(line) = inspector.status_line(state) {
+        d.draw_text(
+            &line,
+            layout.padding_x as i32,
+            text_y,
+            10,
+            Color::new(200, 200, 200, 255),
+        );
+    }
+}
+
+// Watches `monitor_heartbeat` from the UI side
+struct MonitorHealth {
+    last_heartbeat: u64,
+    last_change: Instant,
+}
+
+impl MonitorHealth {
     fn new() -> Self {
         Self {
-            arrow_left: None,
-            arrow_right: None,
-            arrow_up: None,
-            arrow_down: None,
-            bomb: None,
-            focus: None,
-            refresh: None,
-            shoot: None,
+            last_heartbeat: 0,
+            last_change: Instant::now(),
         }
     }
 
-    fn load_icons(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread) {
-        println!("Loading embedded icons...");
+    fn check(&mut self, shared_state: *mut SharedState) {
+        let state = unsafe { &mut *shared_state };
 
-        // Load textures from embedded byte data
-        if let Ok(image) = Image::load_image_from_mem(".png", ARROW_LEFT_PNG) {
-            if let Ok(texture) = rl.load_texture_from_image(thread, &image) {
-                self.arrow_left = Some(texture);
-            }
-        }
-        if let Ok(image) = Image::load_image_from_mem(".png", ARROW_RIGHT_PNG) {
-            if let Ok(texture) = rl.load_texture_from_image(thread, &image) {
-                self.arrow_right = Some(texture);
-            }
-        }
-        if let Ok(image) = Image::load_image_from_mem(".png", ARROW_UP_PNG) {
-            if let Ok(texture) = rl.load_texture_from_image(thread, &image) {
-                self.arrow_up = Some(texture);
-            }
-        }
-        if let Ok(image) = Image::load_image_from_mem(".png", ARROW_DOWN_PNG) {
-            if let Ok(texture) = rl.load_texture_from_image(thread, &image) {
-                self.arrow_down = Some(texture);
-            }
-        }
-        if let Ok(image) = Image::load_image_from_mem(".png", BOMB_PNG) {
-            if let Ok(texture) = rl.load_texture_from_image(thread, &image) {
-                self.bomb = Some(texture);
+        let heartbeat = state.monitor_heartbeat.load(Ordering::Acquire);
+        if heartbeat != self.last_heartbeat {
+            self.last_heartbeat = heartbeat;
+            self.last_change = Instant::now();
+            if state.status.is_active(StatusCode::MonitorUnresponsive) {
+                log::info!("Monitor process is responding again");
+                state.status.clear(StatusCode::MonitorUnresponsive);
             }
+        } else if self.last_change.elapsed() >= HEARTBEAT_TIMEOUT
+            && !state.status.is_active(StatusCode::MonitorUnresponsive)
+        {
+            log::error!("Monitor process stopped updating shared memory");
+            state.status.report(
+                StatusCode::MonitorUnresponsive,
+                "Key monitor is not responding, key states may be stale",
+            );
         }
-        if let Ok(image) = Image::load_image_from_mem(".png", FOCUS_PNG) {
-            if let Ok(texture) = rl.load_texture_from_image(thread, &image) {
-                self.focus = Some(texture);
-            }
+    }
+}
+
+// Tracks which monitor the window is on and that monitor's refresh rate
+struct MonitorWatcher {
+    monitor: i32,
+    refresh_rate: i32,
+    last_check: std::time::Instant,
+}
+
+impl MonitorWatcher {
+    // How often to look for monitor changes (querying every frame is wasteful)
+    const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+    fn new() -> Self {
+        let monitor = raylib::core::window::get_current_monitor();
+        Self {
+            monitor,
+            refresh_rate: raylib::core::window::get_monitor_refresh_rate(monitor),
+            last_check: std::time::Instant::now(),
         }
-        if let Ok(image) = Image::load_image_from_mem(".png", REFRESH_PNG) {
-            if let Ok(texture) = rl.load_texture_from_image(thread, &image) {
-                self.refresh = Some(texture);
-            }
+    }
+
+    // Returns true when the monitor or its refresh rate changed
+    fn poll(&mut self) -> bool {
+        if self.last_check.elapsed() < Self::CHECK_INTERVAL {
+            return false;
         }
-        if let Ok(image) = Image::load_image_from_mem(".png", SHOOT_PNG) {
-            if let Ok(texture) = rl.load_texture_from_image(thread, &image) {
-                self.shoot = Some(texture);
-            }
+        self.last_check = std::time::Instant::now();
+
+        let monitor = raylib::core::window::get_current_monitor();
+        let refresh_rate = raylib::core::window::get_monitor_refresh_rate(monitor);
+        if monitor == self.monitor && refresh_rate == self.refresh_rate {
+            return false;
         }
+
+        self.monitor = monitor;
+        self.refresh_rate = refresh_rate;
+        true
     }
+}
 
-    fn get_icon_texture(&self, keycode: u32) -> Option<&Texture2D> {
-        match keycode {
-            1 => self.refresh.as_ref(),      // S position -> Retry (Refresh icon)
-            3 => self.bomb.as_ref(),         // F position -> Bomb
-            38 => self.arrow_left.as_ref(),  // J position -> Left Arrow
-            40 => self.arrow_up.as_ref(),    // K position -> Up Arrow
-            37 => self.arrow_down.as_ref(),  // L position -> Down Arrow
-            41 => self.arrow_right.as_ref(), // ; position -> Right Arrow
-            51 => self.shoot.as_ref(),       // Backspace -> Shot (EosIconsTroubleshooting)
-            49 => self.focus.as_ref(),       // Space -> Focus Mode
-            _ => None,
-        }
+fn log_frame_rate(policy: &FrameRatePolicy, watcher: &MonitorWatcher, pacer: &FramePacer) {
+    if watcher.refresh_rate <= 0 {
+        log::warn!(
+            monitor = watcher.monitor,
+            assumed_hz = policy.effective_refresh_rate(watcher.refresh_rate);
+            "Monitor reported no refresh rate"
+        );
     }
+    log::info!(
+        monitor = watcher.monitor,
+        refresh_hz = policy.effective_refresh_rate(watcher.refresh_rate),
+        target_fps = policy.target_fps(watcher.refresh_rate),
+        pacing:? = pacer.mode();
+        "Frame rate configured"
+    );
 }
 
 // Global shared memory pointer - will be set during init
@@ -1169,7 +2157,7 @@ static mut SHARED_STATE_PTR: *mut SharedState = ptr::null_mut();
 pub extern "C" fn set_accessibility_permission(has_permission: bool) {
     unsafe {
         if !SHARED_STATE_PTR.is_null() {
-            (*SHARED_STATE_PTR).has_accessibility_permission = has_permission;
+            (*SHARED_STATE_PTR).set_permission(Some(has_permission));
         } else {
             // Fallback to legacy global state if shared memory not initialized
             PERMISSION_STATE.has_accessibility_permission = has_permission;
@@ -1189,43 +2177,137 @@ pub extern "C" fn get_accessibility_permission() -> bool {
     }
 }
 
-// FFI functions for keyboard layout management
+// FFI functions for keyboard layout management. Keys are identified by
+// macOS virtual key codes (kVK_*).
+
+/// # Safety
+/// `name_ptr` is null or a valid C string.
 #[unsafe(no_mangle)]
-pub extern "C" fn set_layout_name(name_ptr: *const std::os::raw::c_char) {
+pub unsafe extern "C" fn set_layout_name(name_ptr: *const std::os::raw::c_char) {
     unsafe {
         if !SHARED_STATE_PTR.is_null() && !name_ptr.is_null() {
             let name_cstr = std::ffi::CStr::from_ptr(name_ptr);
             if let Ok(name_str) = name_cstr.to_str() {
-                (*SHARED_STATE_PTR).set_layout_name(name_str);
+                let layout = &mut (*SHARED_STATE_PTR).layout;
+                if layout.update(|layout| layout.set_name(name_str)) {
+                    log_layout_switch(layout);
+                }
             }
         }
     }
 }
 
+/// `layer`: 0 = base, 1 = Shift, 2 = AltGr (Option)
+///
+/// # Safety
+/// `label_ptr` is null or a valid C string.
 #[unsafe(no_mangle)]
-pub extern "C" fn set_key_label(
-    position_ptr: *const std::os::raw::c_char,
+pub unsafe extern "C" fn set_key_label_for_keycode(
+    keycode: u16,
+    layer: u8,
     label_ptr: *const std::os::raw::c_char,
 ) {
     unsafe {
-        if !SHARED_STATE_PTR.is_null() && !position_ptr.is_null() && !label_ptr.is_null() {
-            let position_cstr = std::ffi::CStr::from_ptr(position_ptr);
+        if !SHARED_STATE_PTR.is_null() && !label_ptr.is_null() {
             let label_cstr = std::ffi::CStr::from_ptr(label_ptr);
+            if let (Some(key), Some(layer), Ok(label_str)) = (
+                PhysicalKey::from_mac_vk(keycode),
+                Layer::from_index(layer),
+                label_cstr.to_str(),
+            ) {
+                // The name stays, so this is never a switch
+                (*SHARED_STATE_PTR)
+                    .layout
+                    .update(|layout| layout.set_label(key, layer, label_str));
+            }
+        }
+    }
+}
 
-            if let (Ok(position_str), Ok(label_str)) = (position_cstr.to_str(), label_cstr.to_str())
-            {
-                (*SHARED_STATE_PTR).set_key_label(position_str, label_str);
+/// Replace the layout name, kind and every label at once, with one label
+/// array per layer (`count` entries each, matching `keycodes`). Keys not in
+/// `keycodes` lose their labels; a null label or label array clears them too.
+/// `kind`: 0 = Latin, 1 = non-Latin, 2 = input method.
+///
+/// # Safety
+/// `name_ptr` is null or a valid C string. Unless `count` is 0, `keycodes` and
+/// each non-null label array hold `count` entries, every label null or a
+/// valid C string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn set_keyboard_layout(
+    name_ptr: *const std::os::raw::c_char,
+    kind: u8,
+    keycodes: *const u16,
+    base_label_ptrs: *const *const std::os::raw::c_char,
+    shift_label_ptrs: *const *const std::os::raw::c_char,
+    altgr_label_ptrs: *const *const std::os::raw::c_char,
+    count: usize,
+) {
+    unsafe {
+        if SHARED_STATE_PTR.is_null() || name_ptr.is_null() {
+            return;
+        }
+        if count > 0 && keycodes.is_null() {
+            return;
+        }
+
+        let mut layout = LayoutLabels::new();
+        if let Ok(name_str) = std::ffi::CStr::from_ptr(name_ptr).to_str() {
+            layout.set_name(name_str);
+        }
+        layout.set_kind(LayoutKind::from_index(kind).unwrap_or(LayoutKind::Latin));
+        let layers = [
+            (Layer::Base, base_label_ptrs),
+            (Layer::Shift, shift_label_ptrs),
+            (Layer::AltGr, altgr_label_ptrs),
+        ];
+        for (layer, label_ptrs) in layers {
+            if label_ptrs.is_null() {
+                continue;
+            }
+            for i in 0..count {
+                let (keycode, label_ptr) = (*keycodes.add(i), *label_ptrs.add(i));
+                if label_ptr.is_null() {
+                    continue;
+                }
+                let label_cstr = std::ffi::CStr::from_ptr(label_ptr);
+                if let (Some(key), Ok(label_str)) =
+                    (PhysicalKey::from_mac_vk(keycode), label_cstr.to_str())
+                {
+                    layout.set_label(key, layer, label_str);
+                }
             }
         }
+        let shared_layout = &mut (*SHARED_STATE_PTR).layout;
+        if shared_layout.publish(&layout) {
+            log_layout_switch(shared_layout);
+        }
+    }
+}
+
+/// Key codes of every key that can have a label. Writes up to `buffer_size` of
+/// them and returns how many there are.
+///
+/// # Safety
+/// `buffer` is null or has room for `buffer_size` key codes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn get_monitored_keycodes(buffer: *mut u16, buffer_size: usize) -> usize {
+    if !buffer.is_null() {
+        for (i, key) in PhysicalKey::all().take(buffer_size).enumerate() {
+            unsafe { *buffer.add(i) = key.mac_vk() };
+        }
     }
+    PhysicalKey::COUNT
 }
 
+/// # Safety
+/// `buffer` is null or has room for `buffer_size` bytes.
 #[unsafe(no_mangle)]
-pub extern "C" fn get_layout_name(buffer: *mut std::os::raw::c_char, buffer_size: usize) {
+pub unsafe extern "C" fn get_layout_name(buffer: *mut std::os::raw::c_char, buffer_size: usize) {
     unsafe {
         if !SHARED_STATE_PTR.is_null() && !buffer.is_null() && buffer_size > 0 {
-            let layout_name = (*SHARED_STATE_PTR).get_layout_name();
-            let name_bytes = layout_name.as_bytes();
+            let layout = (*SHARED_STATE_PTR).layout.snapshot();
+            let name_bytes = layout.name().as_bytes();
             let copy_len = std::cmp::min(name_bytes.len(), buffer_size - 1);
 
             std::ptr::copy_nonoverlapping(name_bytes.as_ptr(), buffer as *mut u8, copy_len);
@@ -1249,3 +2331,254 @@ pub extern "C" fn request_application_close() {
 pub extern "C" fn rust_init() {
     init();
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use input::ScriptedSource;
+
+    // Plays `script` without delays into a fresh shared state
+    fn play(script: &str, show_repeats: bool) -> Rc<RefCell<Box<SharedState>>> {
+        let state = Rc::new(RefCell::new(Box::new(SharedState::new())));
+        let sink_state = state.clone();
+        ScriptedSource::parse(script)
+            .unwrap()
+            .realtime(false)
+            .run(Box::new(move |event| {
+                apply_input_event(&mut sink_state.borrow_mut(), event, None, show_repeats)
+            }))
+            .unwrap();
+        state
+    }
+
+    fn held(state: &SharedState) -> Vec<PhysicalKey> {
+        PhysicalKey::all()
+            .filter(|&key| state.key_states.get_key_state(key))
+            .collect()
+    }
+
+    #[test]
+    fn script_drives_key_states() {
+        let state = play(
+            "0ms press j
+             10ms press space
+             20ms release j
+             30ms press k",
+            false,
+        );
+        let state = state.borrow();
+        assert_eq!(held(&state), [PhysicalKey::K, PhysicalKey::Space]);
+        assert_eq!(state.input_event().0, 4);
+    }
+
+    #[test]
+    fn script_layout_switches_reach_the_history() {
+        let state = play(
+            "0ms layout U.S.
+             10ms press j
+             20ms layout Colemak
+             30ms layout Colemak
+             40ms layout German",
+            false,
+        );
+        let state = state.borrow();
+        assert_eq!(state.layout.snapshot().name(), "German");
+        let history = state.layout.history();
+        // The first layout and a repeated name aren't switches
+        assert_eq!(history.count, 2);
+        let switches: Vec<_> = history
+            .recent()
+            .map(|switch| (switch.from(), switch.to()))
+            .collect();
+        assert_eq!(switches, [("Colemak", "German"), ("U.S.", "Colemak")]);
+        // Key states are untouched
+        assert_eq!(held(&state), [PhysicalKey::J]);
+    }
+
+    // Device names are the longest raw keys
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn key_press_raw_is_cut_at_a_char_boundary() {
+        let mut state = Box::new(SharedState::new());
+        let raw = RawKey::Evdev {
+            code: 36,
+            device: "キーボード".repeat(10).into(),
+        };
+        state.record_key_press(Some(PhysicalKey::J), &raw);
+
+        let press = state.last_key_press();
+        assert_eq!(press.seq, 1);
+        assert_eq!(press.key, Some(PhysicalKey::J));
+        // "evdev code 36 on " and as many 3-byte characters as fit in 95 bytes
+        assert_eq!(
+            press.raw(),
+            format!(
+                "evdev code 36 on {}",
+                "キーボード".repeat(6).chars().take(26).collect::<String>()
+            )
+        );
+    }
+
+    // Repeats are marked for this long in the tests, far longer than they run
+    const REPEAT_WINDOW_NANOS: u64 = 60_000_000_000;
+
+    const HOLD_J: &str = "0ms press j
+                          10ms repeat j
+                          20ms repeat j";
+
+    #[test]
+    fn repeats_are_dropped_unless_shown() {
+        let state = play(HOLD_J, false);
+        let state = state.borrow();
+        assert_eq!(held(&state), [PhysicalKey::J]);
+        assert!(
+            !state
+                .key_states
+                .is_repeating(PhysicalKey::J, REPEAT_WINDOW_NANOS)
+        );
+        // Only the press reached the UI
+        assert_eq!(state.input_event().0, 1);
+    }
+
+    #[test]
+    fn shown_repeats_mark_the_key_until_it_is_released() {
+        let state = play(HOLD_J, true);
+        {
+            let state = state.borrow();
+            assert_eq!(held(&state), [PhysicalKey::J]);
+            assert!(
+                state
+                    .key_states
+                    .is_repeating(PhysicalKey::J, REPEAT_WINDOW_NANOS)
+            );
+            assert_eq!(state.input_event().0, 3);
+        }
+
+        let release = InputEvent::Key {
+            key: PhysicalKey::J,
+            pressed: false,
+            repeat: false,
+            raw: RawKey::Script,
+        };
+        apply_input_event(&mut state.borrow_mut(), release, None, true);
+        let state = state.borrow();
+        assert!(held(&state).is_empty());
+        // Still marked for the window, so a short tap shows it repeated
+        assert!(
+            state
+                .key_states
+                .is_repeating(PhysicalKey::J, REPEAT_WINDOW_NANOS)
+        );
+    }
+
+    #[test]
+    fn repeat_without_a_press() {
+        // E.g. the listener restarted while the key was held
+        let state = play("0ms repeat k", false);
+        assert!(held(&state.borrow()).is_empty());
+
+        let state = play("0ms repeat k", true);
+        assert_eq!(held(&state.borrow()), [PhysicalKey::K]);
+    }
+
+    #[test]
+    fn release_after_release_all() {
+        let state = play("0ms press j\n10ms press k", false);
+        let mut state = state.borrow_mut();
+        apply_input_event(&mut state, InputEvent::ReleaseAll, None, false);
+        assert!(held(&state).is_empty());
+
+        let release = InputEvent::Key {
+            key: PhysicalKey::J,
+            pressed: false,
+            repeat: false,
+            raw: RawKey::Script,
+        };
+        apply_input_event(&mut state, release, None, false);
+        assert!(held(&state).is_empty());
+        // Press, press, release all, release
+        assert_eq!(state.input_event().0, 4);
+    }
+
+    #[test]
+    fn icon_style_defaults_per_state() {
+        let theme = ThemeConfig::default();
+
+        let idle = get_icon_style(&theme, true, false);
+        assert_eq!(idle.tint, Color::new(100, 100, 100, 200));
+        assert_eq!(idle.size, ICON_SIZE);
+        assert_eq!(idle.position, IconPosition::Bottom);
+        let pressed = get_icon_style(&theme, true, true);
+        assert_eq!(pressed.tint, Color::new(50, 80, 120, 230));
+        // Without permission nothing counts as pressed
+        for is_pressed in [false, true] {
+            let disabled = get_icon_style(&theme, false, is_pressed);
+            assert_eq!(disabled.tint, Color::new(150, 50, 50, 150));
+            assert_eq!(disabled.size, ICON_SIZE);
+        }
+    }
+
+    #[test]
+    fn icon_style_comes_from_the_theme() {
+        let mut theme = ThemeConfig::default();
+        theme.icon.pressed.tint = Some([1, 2, 3, 4]);
+        theme.icon.pressed.size = Some(24.0);
+        theme.icon.pressed.position = Some(IconPosition::Center);
+        theme.icon.disabled.position = Some(IconPosition::Top);
+
+        let pressed = get_icon_style(&theme, true, true);
+        assert_eq!(pressed.tint, Color::new(1, 2, 3, 4));
+        assert_eq!(pressed.size, 24.0);
+        assert_eq!(pressed.position, IconPosition::Center);
+        // Each state has its own table
+        let idle = get_icon_style(&theme, true, false);
+        assert_eq!(idle.tint, Color::new(100, 100, 100, 200));
+        assert_eq!(idle.size, ICON_SIZE);
+        // Unset fields keep the state's default
+        let disabled = get_icon_style(&theme, false, false);
+        assert_eq!(disabled.position, IconPosition::Top);
+        assert_eq!(disabled.tint, Color::new(150, 50, 50, 150));
+        assert_eq!(disabled.size, ICON_SIZE);
+    }
+
+    #[test]
+    fn main_labels_follow_shift_only_when_asked() {
+        let mut key_states = KeyStates::new();
+        let mut config = LabelConfig::default();
+        assert_eq!(main_layer(&config, &key_states), Layer::Base);
+
+        key_states.set_key_state(PhysicalKey::ShiftRight, true);
+        assert_eq!(main_layer(&config, &key_states), Layer::Base);
+        config.follow_shift = true;
+        assert_eq!(main_layer(&config, &key_states), Layer::Shift);
+
+        key_states.release_all();
+        assert_eq!(main_layer(&config, &key_states), Layer::Base);
+    }
+
+    #[test]
+    fn shift_layer_falls_back_to_the_base_layer() {
+        let mut layout = LayoutLabels::new();
+        layout.set_label(PhysicalKey::Digit1, Layer::Base, "1");
+        layout.set_label(PhysicalKey::Digit1, Layer::Shift, "!");
+        layout.set_label(PhysicalKey::Semicolon, Layer::Base, "ö");
+        let config = LabelConfig::default();
+        let labels = KeyLabels {
+            layout,
+            main_layer: Layer::Shift,
+            config: &config,
+            remap: None,
+        };
+
+        assert_eq!(get_layout_label(PhysicalKey::Digit1, &labels, false), "!");
+        assert_eq!(
+            get_layout_label(PhysicalKey::Semicolon, &labels, false),
+            "Ö"
+        );
+        // No label on any layer: the key's own name
+        assert_eq!(get_layout_label(PhysicalKey::Escape, &labels, false), "ESC");
+    }
+}
@@ -0,0 +1,305 @@
+use crate::config::LogConfig;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Which side of the fork a log line comes from. Both processes share stderr
+// (and the log file), so every line carries the role and pid.
+#[derive(Clone, Copy)]
+#[repr(u8)]
+pub enum Role {
+    Main = 0,
+    Monitor = 1,
+    Ui = 2,
+    // Forks and restarts the UI process
+    Supervisor = 3,
+}
+
+impl Role {
+    fn name(self) -> &'static str {
+        match self {
+            Role::Main => "main",
+            Role::Monitor => "monitor",
+            Role::Ui => "ui",
+            Role::Supervisor => "supervisor",
+        }
+    }
+}
+
+static ROLE: AtomicU8 = AtomicU8::new(Role::Main as u8);
+
+static LOGGER: Logger = Logger {
+    file: Mutex::new(None),
+};
+
+struct Logger {
+    file: Mutex<Option<LogFile>>,
+}
+
+// Install the logger with stderr output only. Called first thing, so messages
+// from loading the config itself are not lost.
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(env_level().unwrap_or(LevelFilter::Info));
+    }
+}
+
+// Apply the `[log]` section of the config. `THKEYVIS_LOG` wins over the file
+// so verbosity can be raised for a single run.
+pub fn configure(config: &LogConfig) {
+    let level = env_level().unwrap_or_else(|| match LevelFilter::from_str(&config.level) {
+        Ok(level) => level,
+        Err(_) => {
+            log::warn!(level:% = config.level; "Unknown log level in config, using info");
+            LevelFilter::Info
+        }
+    });
+    log::set_max_level(level);
+
+    if let Some(path) = &config.file {
+        match LogFile::open(path, config.max_file_kb * 1024, config.max_files) {
+            Ok(file) => *LOGGER.file.lock().unwrap() = Some(file),
+            Err(error) => {
+                log::error!(path:% = path.display(), error:% = error; "Failed to open log file")
+            }
+        }
+    }
+}
+
+// Call right after fork in each process
+pub fn set_role(role: Role) {
+    ROLE.store(role as u8, Ordering::Relaxed);
+
+    // flock() locks belong to the open file description, which fork shares,
+    // so each process needs its own descriptors for the lock to mean anything
+    if let Ok(mut file) = LOGGER.file.lock()
+        && let Some(file) = file.as_mut()
+    {
+        file.reopen();
+    }
+}
+
+fn current_role() -> Role {
+    match ROLE.load(Ordering::Relaxed) {
+        1 => Role::Monitor,
+        2 => Role::Ui,
+        3 => Role::Supervisor,
+        _ => Role::Main,
+    }
+}
+
+fn env_level() -> Option<LevelFilter> {
+    std::env::var("THKEYVIS_LOG")
+        .ok()
+        .and_then(|value| LevelFilter::from_str(&value).ok())
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format_line(record);
+
+        // One write per line so lines from the two processes don't interleave
+        let _ = std::io::stderr().write_all(line.as_bytes());
+
+        if let Ok(mut file) = self.file.lock()
+            && let Some(file) = file.as_mut()
+        {
+            file.write_line(&line);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+// `12:34:56.789 INFO  [ui 4242] message key=value`
+fn format_line(record: &Record) -> String {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let mut line = format!(
+        "{} {:<5} [{} {}] {}",
+        time_of_day(since_epoch),
+        level_name(record.level()),
+        current_role().name(),
+        std::process::id(),
+        record.args()
+    );
+
+    let mut visitor = KeyValueWriter { line: &mut line };
+    let _ = record.key_values().visit(&mut visitor);
+
+    line.push('\n');
+    line
+}
+
+// `12:34:56.789` in the local time zone, as in log lines. Falls back to UTC,
+// marked `12:34:56.789Z`, if the local time can't be determined.
+pub fn time_of_day(since_epoch: Duration) -> String {
+    let epoch_seconds = since_epoch.as_secs() as libc::time_t;
+    let mut local: libc::tm = unsafe { std::mem::zeroed() };
+    let (seconds_today, marker) =
+        if unsafe { libc::localtime_r(&epoch_seconds, &mut local) }.is_null() {
+            (since_epoch.as_secs() % 86_400, "Z")
+        } else {
+            let seconds_today = local.tm_hour * 3600 + local.tm_min * 60 + local.tm_sec;
+            (seconds_today as u64, "")
+        };
+    format!(
+        "{:02}:{:02}:{:02}.{:03}{}",
+        seconds_today / 3600,
+        seconds_today / 60 % 60,
+        seconds_today % 60,
+        since_epoch.subsec_millis(),
+        marker
+    )
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "ERROR",
+        Level::Warn => "WARN",
+        Level::Info => "INFO",
+        Level::Debug => "DEBUG",
+        Level::Trace => "TRACE",
+    }
+}
+
+struct KeyValueWriter<'a> {
+    line: &'a mut String,
+}
+
+impl<'kvs> log::kv::VisitSource<'kvs> for KeyValueWriter<'_> {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        use std::fmt::Write;
+
+        let value = value.to_string();
+        // Quote values with spaces so lines stay machine-splittable
+        if value.contains(' ') || value.is_empty() {
+            let _ = write!(self.line, " {}={:?}", key, value);
+        } else {
+            let _ = write!(self.line, " {}={}", key, value);
+        }
+        Ok(())
+    }
+}
+
+// Size-rotated log file: `thkeyvis.log`, `thkeyvis.log.1`, ... Both processes
+// append to the same path, so rotation happens under an advisory lock (held on
+// a separate `.lock` file that is never rotated) and a process reopens the log
+// when it notices the other one rotated it.
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    lock: File,
+    max_bytes: u64,
+    max_files: u32,
+}
+
+impl LogFile {
+    fn open(path: &Path, max_bytes: u64, max_files: u32) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Self::open_append(path)?,
+            lock: Self::open_append(&PathBuf::from(format!("{}.lock", path.display())))?,
+            max_bytes,
+            max_files,
+        })
+    }
+
+    fn reopen(&mut self) {
+        if let Ok(reopened) = Self::open(&self.path, self.max_bytes, self.max_files) {
+            *self = reopened;
+        }
+    }
+
+    fn open_append(path: &Path) -> std::io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn write_line(&mut self, line: &str) {
+        let lock_fd = self.lock.as_raw_fd();
+        unsafe {
+            libc::flock(lock_fd, libc::LOCK_EX);
+        }
+
+        // The other process may have rotated the file since our last write
+        let is_current = match (std::fs::metadata(&self.path), self.file.metadata()) {
+            (Ok(on_disk), Ok(ours)) => on_disk.ino() == ours.ino(),
+            _ => false,
+        };
+        if !is_current && let Ok(file) = Self::open_append(&self.path) {
+            self.file = file;
+        }
+
+        if self.max_bytes > 0
+            && self
+                .file
+                .metadata()
+                .map(|metadata| metadata.len() >= self.max_bytes)
+                .unwrap_or(false)
+        {
+            self.rotate();
+        }
+
+        let _ = self.file.write_all(line.as_bytes());
+
+        unsafe {
+            libc::flock(lock_fd, libc::LOCK_UN);
+        }
+    }
+
+    fn rotate(&mut self) {
+        let numbered = |index: u32| PathBuf::from(format!("{}.{}", self.path.display(), index));
+
+        if self.max_files == 0 {
+            let _ = self.file.set_len(0);
+            return;
+        }
+
+        for index in (1..self.max_files).rev() {
+            let _ = std::fs::rename(numbered(index), numbered(index + 1));
+        }
+        let _ = std::fs::rename(&self.path, numbered(1));
+
+        if let Ok(file) = Self::open_append(&self.path) {
+            self.file = file;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_of_day_keeps_milliseconds() {
+        let time = time_of_day(Duration::from_millis(86_400_000 * 365 + 1_234));
+        assert_eq!(time.len(), "12:34:56.789".len(), "{}", time);
+        assert!(time.ends_with(".234"), "{}", time);
+    }
+}
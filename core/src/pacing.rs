@@ -0,0 +1,335 @@
+use crate::config::{FrameConfig, PacingMode};
+use std::time::{Duration, Instant};
+
+// Turns the refresh rate reported by the monitor into a target FPS
+pub struct FrameRatePolicy {
+    target_fps: Option<u32>,
+    refresh_multiplier: u32,
+    fallback_refresh_rate: u32,
+}
+
+impl FrameRatePolicy {
+    pub fn from_config(config: &FrameConfig) -> Self {
+        Self {
+            target_fps: config.target_fps.filter(|&fps| fps > 0),
+            refresh_multiplier: config.refresh_multiplier.max(1),
+            fallback_refresh_rate: config.fallback_refresh_rate.max(1),
+        }
+    }
+
+    // Monitors may report 0 Hz (or garbage) when the rate is unknown
+    pub fn effective_refresh_rate(&self, reported_refresh_rate: i32) -> u32 {
+        if reported_refresh_rate > 0 {
+            reported_refresh_rate as u32
+        } else {
+            self.fallback_refresh_rate
+        }
+    }
+
+    pub fn target_fps(&self, reported_refresh_rate: i32) -> u32 {
+        match self.target_fps {
+            Some(fps) => fps,
+            None => self
+                .effective_refresh_rate(reported_refresh_rate)
+                .saturating_mul(self.refresh_multiplier),
+        }
+    }
+}
+
+// Frame schedule for a fixed target FPS with compensation for missed frames.
+// Kept free of raylib calls so the timing decisions can be exercised with
+// arbitrary `Instant`s.
+pub struct FixedRateSchedule {
+    frame_duration_nanos: u64,
+    start_time: Instant,
+    frame_count: u64,
+}
+
+impl FixedRateSchedule {
+    pub fn new(target_fps: u32, now: Instant) -> Self {
+        Self {
+            // Nanoseconds per frame; at least 1 so absurd rates (e.g. a
+            // saturated refresh multiplier) can't divide by zero later
+            frame_duration_nanos: (1_000_000_000 / target_fps.max(1) as u64).max(1),
+            start_time: now,
+            frame_count: 0,
+        }
+    }
+
+    // Advance to the next frame slot and return how long to sleep until it,
+    // or `None` when the frame is late and the next one should start at once
+    pub fn next_sleep(&mut self, now: Instant) -> Option<Duration> {
+        self.frame_count += 1;
+        let target_frame_time =
+            self.start_time + Duration::from_nanos(self.frame_count * self.frame_duration_nanos);
+
+        // Check if we missed frames (frame took too long)
+        if now > target_frame_time {
+            // Calculate how many frames we're behind
+            let elapsed_since_start = now.duration_since(self.start_time).as_nanos() as u64;
+            let actual_frame_count = elapsed_since_start / self.frame_duration_nanos;
+
+            // More aggressive compensation - skip ahead further to account for system load
+            let compensation_buffer = 2; // Add extra frames to compensate for system spikes
+            if actual_frame_count > self.frame_count {
+                self.frame_count = actual_frame_count + compensation_buffer;
+            }
+
+            // If we're severely behind (more than 5 frames), reset timing to current time
+            let frames_behind = actual_frame_count
+                .saturating_sub(self.frame_count.saturating_sub(compensation_buffer));
+            if frames_behind > 5 {
+                // Reset timing base to prevent perpetual lag
+                self.start_time = now;
+                self.frame_count = 0;
+            }
+            None
+        } else {
+            // We're on time or early, sleep until next frame slot
+            let sleep_duration = target_frame_time - now;
+            // Only sleep if the duration is meaningful (avoid micro-sleeps)
+            if sleep_duration > Duration::from_micros(100) {
+                Some(sleep_duration)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// Pipe used by the monitor process to wake the UI process in event-driven
+// mode. Must be created before fork so both processes share it.
+pub fn wakeup_channel() -> std::io::Result<(WakeupSender, WakeupReceiver)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    for fd in fds {
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+    }
+    Ok((WakeupSender { fd: fds[1] }, WakeupReceiver { fd: fds[0] }))
+}
+
+#[derive(Clone, Copy)]
+pub struct WakeupSender {
+    fd: i32,
+}
+
+impl WakeupSender {
+    pub fn notify(&self) {
+        // A full pipe already has a wake-up pending, so EAGAIN is fine to ignore
+        let byte = 1u8;
+        unsafe {
+            libc::write(self.fd, &byte as *const u8 as *const libc::c_void, 1);
+        }
+    }
+
+    // Called in the supervisor process, which only hands the read end to the
+    // UI processes it starts
+    pub fn close(self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct WakeupReceiver {
+    fd: i32,
+}
+
+impl WakeupReceiver {
+    // Block until woken or until `timeout` passes, then drain pending wake-ups
+    pub fn wait(&self, timeout: Duration) {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        let ready = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+
+        if ready > 0 {
+            let mut buffer = [0u8; 64];
+            while unsafe {
+                libc::read(
+                    self.fd,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            } > 0
+            {}
+        }
+    }
+}
+
+// Decides how long the UI loop waits between frames
+pub struct FramePacer {
+    mode: PacingMode,
+    schedule: FixedRateSchedule,
+    wakeup: Option<WakeupReceiver>,
+    idle_redraw: Duration,
+}
+
+impl FramePacer {
+    pub fn new(
+        mode: PacingMode,
+        target_fps: u32,
+        wakeup: Option<WakeupReceiver>,
+        idle_redraw: Duration,
+    ) -> Self {
+        // Without a wake-up pipe there is nothing to wait on, so fall back to
+        // redrawing at a fixed rate
+        let mode = if mode == PacingMode::EventDriven && wakeup.is_none() {
+            PacingMode::FixedMultiple
+        } else {
+            mode
+        };
+
+        Self {
+            mode,
+            schedule: FixedRateSchedule::new(target_fps, Instant::now()),
+            wakeup,
+            idle_redraw,
+        }
+    }
+
+    pub fn mode(&self) -> PacingMode {
+        self.mode
+    }
+
+    // Restart the fixed-rate schedule, e.g. after moving to another monitor
+    pub fn set_target_fps(&mut self, target_fps: u32) {
+        self.schedule = FixedRateSchedule::new(target_fps, Instant::now());
+    }
+
+    // Call after the frame has been presented
+    pub fn wait_for_next_frame(&mut self) {
+        match self.mode {
+            PacingMode::FixedMultiple => {
+                if let Some(sleep_duration) = self.schedule.next_sleep(Instant::now()) {
+                    std::thread::sleep(sleep_duration);
+                }
+            }
+            // The buffer swap in EndDrawing already blocked until vblank
+            PacingMode::Vsync => {}
+            PacingMode::EventDriven => {
+                if let Some(wakeup) = &self.wakeup {
+                    wakeup.wait(self.idle_redraw);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn zero_hz_falls_back_to_the_configured_rate() {
+        let policy = FrameRatePolicy::from_config(&FrameConfig::default());
+        assert_eq!(policy.effective_refresh_rate(0), 60);
+        assert_eq!(policy.effective_refresh_rate(-1), 60);
+        assert_eq!(policy.target_fps(0), 120);
+
+        let policy = FrameRatePolicy::from_config(&FrameConfig {
+            fallback_refresh_rate: 75,
+            ..FrameConfig::default()
+        });
+        assert_eq!(policy.target_fps(0), 150);
+        // A fallback of 0 would stop the UI
+        let policy = FrameRatePolicy::from_config(&FrameConfig {
+            fallback_refresh_rate: 0,
+            ..FrameConfig::default()
+        });
+        assert_eq!(policy.effective_refresh_rate(0), 1);
+    }
+
+    #[test]
+    fn reported_rate_is_multiplied() {
+        let policy = FrameRatePolicy::from_config(&FrameConfig::default());
+        assert_eq!(policy.effective_refresh_rate(144), 144);
+        assert_eq!(policy.target_fps(144), 288);
+
+        let policy = FrameRatePolicy::from_config(&FrameConfig {
+            refresh_multiplier: 0,
+            ..FrameConfig::default()
+        });
+        assert_eq!(policy.target_fps(60), 60);
+    }
+
+    #[test]
+    fn target_fps_overrides_the_monitor() {
+        let policy = FrameRatePolicy::from_config(&FrameConfig {
+            target_fps: Some(240),
+            ..FrameConfig::default()
+        });
+        assert_eq!(policy.target_fps(60), 240);
+        assert_eq!(policy.target_fps(0), 240);
+
+        // 0 means unset
+        let policy = FrameRatePolicy::from_config(&FrameConfig {
+            target_fps: Some(0),
+            ..FrameConfig::default()
+        });
+        assert_eq!(policy.target_fps(60), 120);
+    }
+
+    #[test]
+    fn on_time_frames_sleep_until_their_slot() {
+        let start = Instant::now();
+        let mut schedule = FixedRateSchedule::new(100, start);
+
+        assert_eq!(schedule.next_sleep(start + ms(3)), Some(ms(7)));
+        assert_eq!(schedule.next_sleep(start + ms(12)), Some(ms(8)));
+        // Too close to the slot to be worth a sleep
+        let almost_there = start + ms(30) - Duration::from_micros(50);
+        assert_eq!(schedule.next_sleep(almost_there), None);
+    }
+
+    #[test]
+    fn late_frame_starts_the_next_one_at_once() {
+        let start = Instant::now();
+        let mut schedule = FixedRateSchedule::new(100, start);
+
+        assert_eq!(schedule.next_sleep(start + ms(15)), None);
+        // Back on the original schedule
+        assert_eq!(schedule.next_sleep(start + ms(16)), Some(ms(4)));
+    }
+
+    #[test]
+    fn long_stall_skips_the_missed_frames() {
+        let start = Instant::now();
+        let mut schedule = FixedRateSchedule::new(100, start);
+        let stalled = start + Duration::from_secs(1);
+
+        assert_eq!(schedule.next_sleep(stalled), None);
+        // No burst of catch-up frames: the next one waits again, at most for
+        // the compensation buffer
+        let sleep = schedule
+            .next_sleep(stalled + ms(1))
+            .expect("frame after a stall should wait");
+        assert!(sleep <= ms(30), "slept {:?}", sleep);
+        // Then the regular cadence
+        let next = stalled + ms(1) + sleep;
+        assert_eq!(schedule.next_sleep(next), Some(ms(10)));
+    }
+
+    #[test]
+    fn absurd_target_fps_does_not_panic() {
+        let start = Instant::now();
+        let mut schedule = FixedRateSchedule::new(u32::MAX, start);
+
+        assert_eq!(schedule.next_sleep(start + ms(5)), None);
+        assert_eq!(schedule.next_sleep(start + ms(6)), None);
+    }
+}
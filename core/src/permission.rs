@@ -0,0 +1,207 @@
+// Permission needed to observe global key events, and how each platform
+// checks for it and tells the user to grant it.
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PermissionStatus {
+    // Not checked yet, or the platform gave no answer
+    Unknown,
+    Denied,
+    Granted,
+    // Was granted earlier in this run and then taken away
+    Revoked,
+}
+
+impl PermissionStatus {
+    // `granted` is the result of a single check; `None` keeps the last known
+    // state
+    pub fn next(self, granted: Option<bool>) -> Self {
+        match (self, granted) {
+            (_, Some(true)) => PermissionStatus::Granted,
+            (PermissionStatus::Granted | PermissionStatus::Revoked, Some(false)) => {
+                PermissionStatus::Revoked
+            }
+            (_, Some(false)) => PermissionStatus::Denied,
+            (status, None) => status,
+        }
+    }
+}
+
+pub trait PermissionProvider: Send {
+    // Shown in the banner, e.g. "Input Monitoring"
+    fn permission_name(&self) -> &'static str;
+
+    // `None` when the platform can't tell
+    fn check(&self) -> Option<bool>;
+
+    // Second banner line: how to grant the permission
+    fn guidance(&self) -> &'static str;
+
+    // Whether the banner shows an "Open Settings" button
+    fn can_open_settings(&self) -> bool {
+        false
+    }
+
+    fn open_settings(&self) {}
+
+    fn banner_title(&self, status: PermissionStatus) -> String {
+        match status {
+            PermissionStatus::Unknown => format!("Checking {} permission", self.permission_name()),
+            PermissionStatus::Revoked => {
+                format!("{} permission was revoked", self.permission_name())
+            }
+            _ => format!("{} permission required", self.permission_name()),
+        }
+    }
+}
+
+pub fn platform_provider() -> Box<dyn PermissionProvider> {
+    #[cfg(target_os = "macos")]
+    {
+        Box::new(MacOsProvider)
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        Box::new(LinuxProvider::new())
+    }
+}
+
+#[cfg(target_os = "macos")]
+struct MacOsProvider;
+
+#[cfg(target_os = "macos")]
+impl PermissionProvider for MacOsProvider {
+    fn permission_name(&self) -> &'static str {
+        "Input Monitoring"
+    }
+
+    // Input Monitoring, the same check as the Swift shell; Accessibility
+    // (AXIsProcessTrusted) is a different permission and doesn't let the
+    // event tap listen
+    fn check(&self) -> Option<bool> {
+        // From IOKit/hidsystem/IOHIDLib.h
+        const REQUEST_TYPE_LISTEN_EVENT: u32 = 1;
+        const ACCESS_TYPE_GRANTED: u32 = 0;
+
+        #[link(name = "IOKit", kind = "framework")]
+        unsafe extern "C" {
+            fn IOHIDCheckAccess(request_type: u32) -> u32;
+        }
+
+        // Denied and not asked yet (kIOHIDAccessTypeUnknown) both mean the
+        // tap can't listen
+        Some(unsafe { IOHIDCheckAccess(REQUEST_TYPE_LISTEN_EVENT) } == ACCESS_TYPE_GRANTED)
+    }
+
+    fn guidance(&self) -> &'static str {
+        "Allow THKeyVis under Privacy & Security > Input Monitoring"
+    }
+
+    fn can_open_settings(&self) -> bool {
+        true
+    }
+
+    fn open_settings(&self) {
+        let _ = std::process::Command::new("open")
+            .arg("x-apple.systempreferences:com.apple.preference.security?Privacy_ListenEvent")
+            .spawn();
+    }
+}
+
+// Reading keyboards directly needs read access to the evdev nodes, which
+// distributions grant to the `input` group
+#[cfg(not(target_os = "macos"))]
+struct LinuxProvider {
+    in_input_group: bool,
+}
+
+#[cfg(not(target_os = "macos"))]
+impl LinuxProvider {
+    fn new() -> Self {
+        Self {
+            in_input_group: in_group("input"),
+        }
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+impl PermissionProvider for LinuxProvider {
+    fn permission_name(&self) -> &'static str {
+        "Input device access"
+    }
+
+    fn check(&self) -> Option<bool> {
+        let entries = std::fs::read_dir("/dev/input").ok()?;
+        let devices: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("event"))
+            .map(|entry| entry.path())
+            .collect();
+
+        // No devices at all (e.g. in a container) says nothing about access
+        if devices.is_empty() {
+            return None;
+        }
+
+        Some(devices.iter().any(|path| {
+            let Ok(path) = std::ffi::CString::new(path.as_os_str().as_encoded_bytes()) else {
+                return false;
+            };
+            unsafe { libc::access(path.as_ptr(), libc::R_OK) == 0 }
+        }))
+    }
+
+    fn guidance(&self) -> &'static str {
+        if self.in_input_group {
+            "Check the permissions of /dev/input/event* (udev rules)"
+        } else {
+            "Run: sudo usermod -aG input $USER, then log in again"
+        }
+    }
+}
+
+// Whether this process runs with `name` as its effective or a supplementary
+// group
+#[cfg(not(target_os = "macos"))]
+fn in_group(name: &str) -> bool {
+    let Ok(name) = std::ffi::CString::new(name) else {
+        return false;
+    };
+    let group = unsafe { libc::getgrnam(name.as_ptr()) };
+    if group.is_null() {
+        return false;
+    }
+    let gid = unsafe { (*group).gr_gid };
+    if unsafe { libc::getegid() } == gid {
+        return true;
+    }
+
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if count <= 0 {
+        return false;
+    }
+    let mut groups = vec![0; count as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    count > 0 && groups[..count as usize].contains(&gid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_follows_checks() {
+        let status = PermissionStatus::Unknown.next(None);
+        assert_eq!(status, PermissionStatus::Unknown);
+        let status = status.next(Some(false));
+        assert_eq!(status, PermissionStatus::Denied);
+        let status = status.next(Some(true));
+        assert_eq!(status, PermissionStatus::Granted);
+        // A failed check keeps what was known
+        assert_eq!(status.next(None), PermissionStatus::Granted);
+        let status = status.next(Some(false));
+        assert_eq!(status, PermissionStatus::Revoked);
+        assert_eq!(status.next(Some(false)), PermissionStatus::Revoked);
+        assert_eq!(status.next(Some(true)), PermissionStatus::Granted);
+    }
+}
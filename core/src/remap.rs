@@ -0,0 +1,124 @@
+// Remap mode, ported from the legacy SwiftUI version. For setups where a key
+// remapper changes what some keys send, each of those keys also shows what it
+// is remapped to, at the bottom where the action icon is, and an extra left
+// Shift key is drawn.
+
+use crate::bindings::Slot;
+use crate::config::RemapConfig;
+use crate::keys::PhysicalKey;
+
+// The legacy table, used when `[remap.keys]` is not set. Legacy showed arrows
+// and "⇧ (broken)" for Space, which the default font can't draw.
+const LEGACY_TABLE: [(PhysicalKey, &str); 8] = [
+    (PhysicalKey::S, "R"),
+    (PhysicalKey::F, "X"),
+    (PhysicalKey::J, "LEFT"),
+    (PhysicalKey::K, "UP"),
+    (PhysicalKey::L, "DOWN"),
+    (PhysicalKey::Semicolon, "RIGHT"),
+    (PhysicalKey::Backspace, "Z"),
+    (PhysicalKey::Space, "SHIFT (broken)"),
+];
+
+// Arrows as written in `[remap.keys]`, and how they are drawn
+const ARROWS: [(&str, &str); 4] = [("←", "LEFT"), ("↑", "UP"), ("↓", "DOWN"), ("→", "RIGHT")];
+
+pub struct RemapTable {
+    labels: Vec<(PhysicalKey, String)>,
+}
+
+impl RemapTable {
+    // Unknown key names are skipped
+    pub fn from_config(config: &RemapConfig) -> Self {
+        let Some(keys) = &config.keys else {
+            return Self {
+                labels: LEGACY_TABLE
+                    .iter()
+                    .map(|&(key, label)| (key, label.to_string()))
+                    .collect(),
+            };
+        };
+
+        let labels = keys
+            .iter()
+            .filter_map(|(name, label)| match PhysicalKey::from_name(name) {
+                Some(key) => Some((key, drawable(label))),
+                None => {
+                    log::warn!(key = name.as_str(); "Unknown key in [remap.keys], ignoring it");
+                    None
+                }
+            })
+            .collect();
+        Self { labels }
+    }
+
+    pub fn label(&self, key: PhysicalKey) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(remapped, _)| *remapped == key)
+            .map(|(_, label)| label.as_str())
+    }
+}
+
+// The default font has no arrows
+fn drawable(label: &str) -> String {
+    match ARROWS.iter().find(|&&(arrow, _)| arrow == label) {
+        Some(&(_, name)) => name.to_string(),
+        None => label.to_string(),
+    }
+}
+
+// Whether the slot's icon already says `label`, e.g. the left arrow icon for
+// "LEFT"; such keys show only the icon, as in legacy
+pub fn icon_shows(slot: Slot, label: &str) -> bool {
+    let arrow = match slot {
+        Slot::Left => "LEFT",
+        Slot::Up => "UP",
+        Slot::Down => "DOWN",
+        Slot::Right => "RIGHT",
+        _ => return false,
+    };
+    label == arrow
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(keys: &[(&str, &str)]) -> RemapTable {
+        RemapTable::from_config(&RemapConfig {
+            keys: Some(
+                keys.iter()
+                    .map(|&(key, label)| (key.to_string(), label.to_string()))
+                    .collect(),
+            ),
+            ..RemapConfig::default()
+        })
+    }
+
+    #[test]
+    fn legacy_table_is_drawable() {
+        let legacy = RemapTable::from_config(&RemapConfig::default());
+        for (_, label) in &legacy.labels {
+            assert!(label.is_ascii(), "{label}");
+        }
+        assert_eq!(legacy.label(PhysicalKey::J), Some("LEFT"));
+        assert_eq!(legacy.label(PhysicalKey::A), None);
+    }
+
+    #[test]
+    fn arrows_are_spelled_out() {
+        let remap = table(&[("h", "←"), ("z", "→"), ("x", "SHOT")]);
+        assert_eq!(remap.label(PhysicalKey::H), Some("LEFT"));
+        assert_eq!(remap.label(PhysicalKey::Z), Some("RIGHT"));
+        assert_eq!(remap.label(PhysicalKey::X), Some("SHOT"));
+    }
+
+    #[test]
+    fn only_the_matching_arrow_slot_hides_the_label() {
+        assert!(icon_shows(Slot::Left, "LEFT"));
+        // e.g. `left` rebound to a key the remapper turns into the right arrow
+        assert!(!icon_shows(Slot::Left, "RIGHT"));
+        assert!(!icon_shows(Slot::Shot, "LEFT"));
+    }
+}
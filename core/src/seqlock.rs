@@ -0,0 +1,86 @@
+// A value in shared memory that one process writes and the other reads as a
+// whole. The writer makes the sequence odd while it writes; readers copy the
+// value and retry until the sequence was the same even number before and
+// after. Writes are rare and short, so retrying is cheap; a writer that never
+// finishes only delays readers by a bounded number of retries.
+
+use std::sync::atomic::{AtomicU64, Ordering, fence};
+
+// A write copies a few KB at most; this many spins take far longer
+const MAX_RETRIES: u32 = 10_000;
+
+#[repr(C)]
+pub struct SeqLock<T: Copy> {
+    // Odd while a write is in progress
+    seq: AtomicU64,
+    value: T,
+}
+
+impl<T: Copy> SeqLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            value,
+        }
+    }
+
+    pub fn write(&mut self, change: impl FnOnce(&mut T)) {
+        // Even, in case a UI process crashed halfway through its last write
+        let seq = self.seq.load(Ordering::Relaxed) & !1;
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        change(&mut self.value);
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    // After `MAX_RETRIES` the copy is taken anyway: a sequence that stays odd
+    // that long means the writer died mid-write (a crashed UI process), and
+    // its next write starts over from an even sequence
+    pub fn read(&self) -> T {
+        for _ in 0..MAX_RETRIES {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq.is_multiple_of(2) {
+                let copy = self.copy();
+                fence(Ordering::Acquire);
+                if self.seq.load(Ordering::Relaxed) == seq {
+                    return copy;
+                }
+            }
+            std::hint::spin_loop();
+        }
+        self.copy()
+    }
+
+    fn copy(&self) -> T {
+        // Volatile: the other process writes behind our back
+        unsafe { std::ptr::read_volatile(&self.value) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_are_read_back() {
+        let mut lock = SeqLock::new(0u64);
+        lock.write(|value| *value = 7);
+        assert_eq!(lock.read(), 7);
+        assert_eq!(lock.seq.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn abandoned_write_does_not_block() {
+        let mut lock = SeqLock::new([1u8; 4]);
+        // A writer that died between its two increments
+        lock.seq.store(3, Ordering::Relaxed);
+        lock.value = [2, 2, 0, 0];
+        assert_eq!(lock.read(), [2, 2, 0, 0]);
+
+        lock.write(|value| *value = [5; 4]);
+        assert_eq!(lock.seq.load(Ordering::Relaxed), 4);
+        assert_eq!(lock.read(), [5; 4]);
+    }
+}
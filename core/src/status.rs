@@ -0,0 +1,163 @@
+// Error/status channel in shared memory. The monitor process (and the UI
+// process itself) report problems here so the UI can show why the keys stay
+// dark instead of only printing to stderr. Each slot is written under its own
+// sequence lock, so the other process never sees half of a message that is
+// being replaced.
+
+use crate::seqlock::SeqLock;
+
+// Max message length (null-terminated, so 95 bytes of text)
+const MESSAGE_SIZE: usize = 96;
+
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StatusCode {
+    // rdev::listen failed or stopped; the monitor keeps retrying
+    ListenerFailed = 0,
+    // The monitor process stopped updating shared memory
+    MonitorUnresponsive = 1,
+    // One or more icons could not be loaded
+    IconLoadFailed = 2,
+    // Event-driven pacing was requested but the wake-up pipe is unavailable
+    WakeupUnavailable = 3,
+}
+
+impl StatusCode {
+    const COUNT: usize = 4;
+
+    // In order of display priority (most severe first)
+    const ALL: [StatusCode; Self::COUNT] = [
+        StatusCode::MonitorUnresponsive,
+        StatusCode::ListenerFailed,
+        StatusCode::IconLoadFailed,
+        StatusCode::WakeupUnavailable,
+    ];
+
+    pub fn severity(self) -> Severity {
+        match self {
+            StatusCode::ListenerFailed | StatusCode::MonitorUnresponsive => Severity::Error,
+            StatusCode::IconLoadFailed | StatusCode::WakeupUnavailable => Severity::Warning,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SlotStatus {
+    active: bool,
+    message: [u8; MESSAGE_SIZE],
+}
+
+// One slot per status code, so a problem stays visible until whoever reported
+// it clears it (e.g. the listener recovering)
+#[repr(C)]
+pub struct StatusChannel {
+    slots: [SeqLock<SlotStatus>; StatusCode::COUNT],
+}
+
+pub struct StatusEntry {
+    pub code: StatusCode,
+    pub message: String,
+}
+
+impl StatusChannel {
+    pub fn new() -> Self {
+        Self {
+            slots: std::array::from_fn(|_| {
+                SeqLock::new(SlotStatus {
+                    active: false,
+                    message: [0; MESSAGE_SIZE],
+                })
+            }),
+        }
+    }
+
+    pub fn report(&mut self, code: StatusCode, message: &str) {
+        self.slots[code as usize].write(|status| {
+            // Clear the array first
+            status.message = [0; MESSAGE_SIZE];
+
+            // Copy the string bytes without splitting a UTF-8 sequence
+            let mut copy_len = std::cmp::min(message.len(), MESSAGE_SIZE - 1); // Leave room for null terminator
+            while !message.is_char_boundary(copy_len) {
+                copy_len -= 1;
+            }
+            status.message[..copy_len].copy_from_slice(&message.as_bytes()[..copy_len]);
+            status.active = true;
+        });
+    }
+
+    pub fn clear(&mut self, code: StatusCode) {
+        self.slots[code as usize].write(|status| status.active = false);
+    }
+
+    pub fn is_active(&self, code: StatusCode) -> bool {
+        self.slots[code as usize].read().active
+    }
+
+    // The status to show in the banner, if any
+    pub fn most_severe(&self) -> Option<StatusEntry> {
+        StatusCode::ALL.into_iter().find_map(|code| {
+            let status = self.slots[code as usize].read();
+            if !status.active {
+                return None;
+            }
+            let message = &status.message;
+            let null_pos = message.iter().position(|&b| b == 0).unwrap_or(MESSAGE_SIZE);
+            Some(StatusEntry {
+                code,
+                message: String::from_utf8_lossy(&message[..null_pos]).to_string(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_severe_status_wins() {
+        let mut channel = StatusChannel::new();
+        assert!(channel.most_severe().is_none());
+
+        channel.report(StatusCode::IconLoadFailed, "Failed to load icons: shot");
+        channel.report(StatusCode::ListenerFailed, "Key listener stopped, retrying");
+        let status = channel.most_severe().unwrap();
+        assert_eq!(status.code, StatusCode::ListenerFailed);
+        assert_eq!(status.message, "Key listener stopped, retrying");
+
+        channel.clear(StatusCode::ListenerFailed);
+        assert!(!channel.is_active(StatusCode::ListenerFailed));
+        assert_eq!(
+            channel.most_severe().unwrap().code,
+            StatusCode::IconLoadFailed
+        );
+    }
+
+    #[test]
+    fn re_report_replaces_the_whole_message() {
+        let mut channel = StatusChannel::new();
+        channel.report(
+            StatusCode::ListenerFailed,
+            "Key listener failed (a long reason), retrying",
+        );
+        channel.report(StatusCode::ListenerFailed, "Short");
+        assert_eq!(channel.most_severe().unwrap().message, "Short");
+    }
+
+    #[test]
+    fn long_message_is_cut_at_a_char_boundary() {
+        let mut channel = StatusChannel::new();
+        // 2-byte characters, so byte 95 falls inside one
+        channel.report(StatusCode::IconLoadFailed, &"é".repeat(60));
+        let message = channel.most_severe().unwrap().message;
+        assert_eq!(message, "é".repeat(47));
+    }
+}
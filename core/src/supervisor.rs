@@ -0,0 +1,271 @@
+use std::time::{Duration, Instant};
+
+// Process exit codes used by both processes
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FORK_FAILED: i32 = 1;
+// The UI kept crashing and the restart budget ran out
+pub const EXIT_UI_CRASHED: i32 = 2;
+// UI and supervisor processes only: the parent process went away
+pub const EXIT_PARENT_GONE: i32 = 3;
+
+// Restart backoff: 0.5s, 1s, 2s, ... capped at 10s
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+// A UI that stayed up this long is considered healthy again
+const STABLE_UPTIME: Duration = Duration::from_secs(30);
+// Give up after this many crashes without a stable run in between
+const MAX_CONSECUTIVE_CRASHES: u32 = 5;
+// How often the supervisor checks on the UI and the monitor process
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub enum ChildExit {
+    Exited(i32),
+    Signaled(i32),
+}
+
+impl std::fmt::Display for ChildExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ChildExit::Exited(code) => write!(f, "exit code {}", code),
+            ChildExit::Signaled(signal) => write!(f, "signal {}", signal),
+        }
+    }
+}
+
+impl ChildExit {
+    fn is_clean(&self) -> bool {
+        matches!(self, ChildExit::Exited(EXIT_OK))
+    }
+}
+
+enum Wait {
+    Exited(ChildExit),
+    // The process that should outlive the child is gone
+    ParentGone,
+    // waitpid failed
+    Lost,
+}
+
+// Blocks until the child exits, retrying on EINTR. With `parent_pid`, also
+// stops waiting once that process is gone.
+fn wait_for_child(pid: i32, parent_pid: Option<i32>) -> Wait {
+    let flags = if parent_pid.is_some() {
+        libc::WNOHANG
+    } else {
+        0
+    };
+    loop {
+        let mut status = 0;
+        let result = unsafe { libc::waitpid(pid, &mut status, flags) };
+        if result == pid {
+            if libc::WIFEXITED(status) {
+                return Wait::Exited(ChildExit::Exited(libc::WEXITSTATUS(status)));
+            }
+            if libc::WIFSIGNALED(status) {
+                return Wait::Exited(ChildExit::Signaled(libc::WTERMSIG(status)));
+            }
+            // Stopped/continued: keep waiting
+            continue;
+        }
+        if result == 0 {
+            // WNOHANG and still running
+            if parent_pid.is_some_and(|parent_pid| !parent_is_alive(parent_pid)) {
+                return Wait::ParentGone;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+        if result == -1 && std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {
+            continue;
+        }
+        return Wait::Lost;
+    }
+}
+
+// Decides whether and when a crashed UI process is restarted
+pub struct RestartPolicy {
+    backoff: Duration,
+    consecutive_crashes: u32,
+}
+
+impl RestartPolicy {
+    pub fn new() -> Self {
+        Self {
+            backoff: INITIAL_BACKOFF,
+            consecutive_crashes: 0,
+        }
+    }
+
+    // Returns the delay before the next restart, or `None` to give up
+    pub fn on_crash(&mut self, uptime: Duration) -> Option<Duration> {
+        if uptime >= STABLE_UPTIME {
+            self.backoff = INITIAL_BACKOFF;
+            self.consecutive_crashes = 0;
+        }
+
+        self.consecutive_crashes += 1;
+        if self.consecutive_crashes > MAX_CONSECUTIVE_CRASHES {
+            return None;
+        }
+
+        let delay = self.backoff;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        Some(delay)
+    }
+}
+
+// Runs in the supervisor process: waits on the UI process and restarts it
+// when it crashes. Returns the exit code for the whole application once the
+// UI is closed normally or the restart budget is exhausted, or
+// `EXIT_PARENT_GONE` (after stopping the UI) when the monitor process dies.
+//
+// The supervisor process is forked from the main process before that starts
+// any threads, and never starts one itself. `spawn` forks new UI processes
+// from it, so a restart is as safe as the first fork: no other thread can be
+// holding a lock (the logger's, malloc's, the Objective-C runtime's) that the
+// new process would then wait on forever.
+pub fn supervise(
+    mut pid: i32,
+    monitor_pid: i32,
+    mut spawn: impl FnMut() -> Option<i32>,
+    close_requested: impl Fn() -> bool,
+) -> i32 {
+    let mut policy = RestartPolicy::new();
+    let mut started_at = Instant::now();
+
+    loop {
+        let exit = match wait_for_child(pid, Some(monitor_pid)) {
+            Wait::Exited(exit) => exit,
+            Wait::ParentGone => {
+                log::warn!(ui_pid = pid; "Monitor process is gone, stopping the UI");
+                unsafe {
+                    libc::kill(pid, libc::SIGTERM);
+                }
+                return EXIT_PARENT_GONE;
+            }
+            Wait::Lost => {
+                log::error!(ui_pid = pid; "Lost track of UI process");
+                return EXIT_UI_CRASHED;
+            }
+        };
+
+        if exit.is_clean() || close_requested() {
+            log::info!(ui_pid = pid, status:% = exit; "UI process exited");
+            return EXIT_OK;
+        }
+
+        let uptime = started_at.elapsed();
+        log::error!(ui_pid = pid, status:% = exit, uptime_ms = uptime.as_millis() as u64; "UI process crashed");
+
+        let Some(delay) = policy.on_crash(uptime) else {
+            log::error!("UI process keeps crashing, giving up");
+            return EXIT_UI_CRASHED;
+        };
+        log::info!(delay_ms = delay.as_millis() as u64; "Restarting UI process");
+        std::thread::sleep(delay);
+
+        pid = match spawn() {
+            Some(pid) => pid,
+            None => return EXIT_FORK_FAILED,
+        };
+        started_at = Instant::now();
+    }
+}
+
+// Runs in the monitor process: the application's exit code, once the
+// supervisor process exits
+pub fn wait_for_supervisor(pid: i32) -> i32 {
+    match wait_for_child(pid, None) {
+        Wait::Exited(ChildExit::Exited(code)) => code,
+        Wait::Exited(exit) => {
+            log::error!(supervisor_pid = pid, status:% = exit; "Supervisor process died");
+            EXIT_UI_CRASHED
+        }
+        Wait::ParentGone | Wait::Lost => {
+            log::error!(supervisor_pid = pid; "Lost track of supervisor process");
+            EXIT_UI_CRASHED
+        }
+    }
+}
+
+// Called in the supervisor and UI processes right after fork. On Linux the
+// kernel delivers SIGTERM when the forking thread of the parent dies;
+// everywhere else the parent is polled with `parent_is_alive`.
+pub fn watch_parent() {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+    }
+}
+
+// Orphaned processes get reparented, so a changed ppid means the parent died
+pub fn parent_is_alive(parent_pid: i32) -> bool {
+    unsafe { libc::getppid() == parent_pid }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHORT_RUN: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn backoff_doubles_until_the_budget_runs_out() {
+        let mut policy = RestartPolicy::new();
+        let delays: Vec<_> = (0..MAX_CONSECUTIVE_CRASHES)
+            .map(|_| policy.on_crash(SHORT_RUN))
+            .collect();
+        assert_eq!(
+            delays,
+            [500, 1000, 2000, 4000, 8000].map(|ms| Some(Duration::from_millis(ms)))
+        );
+        assert_eq!(policy.on_crash(SHORT_RUN), None);
+    }
+
+    #[test]
+    fn stable_run_resets_backoff_and_budget() {
+        let mut policy = RestartPolicy::new();
+        for _ in 0..MAX_CONSECUTIVE_CRASHES {
+            policy.on_crash(SHORT_RUN);
+        }
+
+        assert_eq!(policy.on_crash(STABLE_UPTIME), Some(INITIAL_BACKOFF));
+        for _ in 1..MAX_CONSECUTIVE_CRASHES {
+            assert!(policy.on_crash(SHORT_RUN).is_some());
+        }
+        assert_eq!(policy.on_crash(SHORT_RUN), None);
+    }
+
+    // A child that exits right away with `code`
+    fn spawn_exiting(code: i32) -> Option<i32> {
+        match unsafe { libc::fork() } {
+            -1 => None,
+            0 => unsafe { libc::_exit(code) },
+            pid => Some(pid),
+        }
+    }
+
+    #[test]
+    fn supervise_restarts_a_crashed_ui_until_it_exits_cleanly() {
+        let first = spawn_exiting(1).unwrap();
+        let mut spawned = 0;
+        let exit_code = supervise(
+            first,
+            // The "monitor process" is the test runner's parent
+            unsafe { libc::getppid() },
+            || {
+                spawned += 1;
+                spawn_exiting(EXIT_OK)
+            },
+            || false,
+        );
+        assert_eq!(exit_code, EXIT_OK);
+        assert_eq!(spawned, 1);
+    }
+
+    #[test]
+    fn supervisor_exit_code_reaches_the_monitor() {
+        let pid = spawn_exiting(EXIT_UI_CRASHED).unwrap();
+        assert_eq!(wait_for_supervisor(pid), EXIT_UI_CRASHED);
+    }
+}
@@ -0,0 +1,156 @@
+// "Switched to Colemak" over the keyboard for a while after each layout
+// switch, so an accidental toggle mid-run doesn't go unnoticed. The history
+// behind it lives in `SharedLayout` (see labels.rs). Takes the frame's time
+// instead of reading the clock, so the timing can be tested.
+
+use std::time::{Duration, Instant};
+
+use crate::SharedState;
+
+// The toast fades out over the end of its time
+const FADE: Duration = Duration::from_millis(300);
+
+pub struct LayoutToast {
+    // Zero turns the toast off
+    duration: Duration,
+    // `SwitchHistory::count` already shown
+    last_count: u64,
+    shown: Option<(String, Instant)>,
+}
+
+impl LayoutToast {
+    pub fn new(state: &SharedState, duration: Duration) -> Self {
+        Self {
+            duration,
+            // Switches from before this UI process started don't count
+            last_count: state.layout.history().count,
+            shown: None,
+        }
+    }
+
+    // Call once per frame. A switch replaces the toast shown and starts its
+    // time over.
+    pub fn update(&mut self, state: &SharedState, now: Instant) {
+        let history = state.layout.history();
+        if history.count == self.last_count {
+            return;
+        }
+        self.last_count = history.count;
+
+        if let Some(switch) = history.latest()
+            && !self.duration.is_zero()
+        {
+            self.shown = Some((format!("Switched to {}", switch.to()), now));
+        }
+    }
+
+    // The text and its opacity (0 to 1) while the toast is up
+    pub fn text(&self, now: Instant) -> Option<(&str, f32)> {
+        let (text, shown_at) = self.shown.as_ref()?;
+        let left = self
+            .duration
+            .checked_sub(now.saturating_duration_since(*shown_at))
+            .filter(|left| !left.is_zero())?;
+        let fade = FADE.min(self.duration);
+        Some((text, (left.as_secs_f32() / fade.as_secs_f32()).min(1.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::InputEvent;
+
+    fn switch_to(state: &mut SharedState, name: &str) {
+        let event = InputEvent::Layout {
+            name: name.to_string(),
+        };
+        crate::apply_input_event(state, event, None, false);
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn shows_each_switch_for_its_time() {
+        let mut state = Box::new(SharedState::new());
+        switch_to(&mut state, "U.S.");
+        // A switch from before the UI started
+        switch_to(&mut state, "German");
+        let start = Instant::now();
+        let mut toast = LayoutToast::new(&state, ms(2000));
+        toast.update(&state, start);
+        assert_eq!(toast.text(start), None);
+
+        switch_to(&mut state, "Colemak");
+        toast.update(&state, start);
+        assert_eq!(toast.text(start), Some(("Switched to Colemak", 1.0)));
+        // Nothing new, so later frames keep the same toast
+        toast.update(&state, start + ms(500));
+        assert_eq!(
+            toast.text(start + ms(1999)).map(|(text, _)| text),
+            Some("Switched to Colemak")
+        );
+        assert_eq!(toast.text(start + ms(2000)), None);
+    }
+
+    #[test]
+    fn new_switch_replaces_the_toast() {
+        let mut state = Box::new(SharedState::new());
+        switch_to(&mut state, "U.S.");
+        let start = Instant::now();
+        let mut toast = LayoutToast::new(&state, ms(2000));
+
+        switch_to(&mut state, "Colemak");
+        toast.update(&state, start);
+        switch_to(&mut state, "German");
+        toast.update(&state, start + ms(1500));
+
+        let (text, opacity) = toast.text(start + ms(2500)).unwrap();
+        assert_eq!(text, "Switched to German");
+        assert_eq!(opacity, 1.0);
+        assert_eq!(toast.text(start + ms(3500)), None);
+    }
+
+    #[test]
+    fn fades_out_at_the_end() {
+        let mut state = Box::new(SharedState::new());
+        switch_to(&mut state, "U.S.");
+        let start = Instant::now();
+        let mut toast = LayoutToast::new(&state, ms(2000));
+        switch_to(&mut state, "Colemak");
+        toast.update(&state, start);
+
+        // In percent, to compare without float noise
+        let opacity = |toast: &LayoutToast, at| {
+            toast
+                .text(start + ms(at))
+                .map(|(_, opacity)| (opacity * 100.0).round() as u32)
+        };
+        assert_eq!(opacity(&toast, 1700), Some(100));
+        assert_eq!(opacity(&toast, 1850), Some(50));
+        assert_eq!(opacity(&toast, 1970), Some(10));
+        assert_eq!(opacity(&toast, 2000), None);
+
+        // Shorter than the fade: fades all the way
+        let mut toast = LayoutToast::new(&state, ms(100));
+        switch_to(&mut state, "German");
+        toast.update(&state, start);
+        assert_eq!(opacity(&toast, 0), Some(100));
+        assert_eq!(opacity(&toast, 50), Some(50));
+    }
+
+    #[test]
+    fn zero_duration_turns_it_off() {
+        let mut state = Box::new(SharedState::new());
+        switch_to(&mut state, "U.S.");
+        let start = Instant::now();
+        let mut toast = LayoutToast::new(&state, Duration::ZERO);
+        switch_to(&mut state, "Colemak");
+        toast.update(&state, start);
+        assert_eq!(toast.text(start), None);
+        // The switch is still in the history for the log
+        assert_eq!(state.layout.history().count, 1);
+    }
+}
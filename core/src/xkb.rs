@@ -0,0 +1,446 @@
+// Linux layout provider: the layout name and key labels come from an xkb
+// keymap, the way Swift fills them from UCKeyTranslate on macOS.
+// libxkbcommon is loaded at runtime so the app still starts (with QWERTY
+// labels) on systems without it.
+//
+// xkbcommon can't ask the display server which layout is active, so the
+// first one is assumed at startup. From there every key event is fed to an
+// xkb state, so layout switches bound in the keymap (e.g.
+// `options = "grp:alt_shift_toggle"`) are followed. Input methods (IBus,
+// Fcitx) sit above the keymap and aren't visible here.
+
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::path::Path;
+
+use crate::SharedState;
+use crate::config::XkbConfig;
+use crate::keys::PhysicalKey;
+use crate::labels::{Layer, LayoutKind, LayoutLabels};
+
+// From xkbcommon.h
+const XKB_KEYMAP_FORMAT_TEXT_V1: c_int = 1;
+const XKB_KEY_UP: c_int = 0;
+const XKB_KEY_DOWN: c_int = 1;
+const XKB_STATE_LAYOUT_EFFECTIVE: c_int = 1 << 7;
+// xkb keycodes are evdev codes shifted by 8
+const EVDEV_OFFSET: u32 = 8;
+
+#[repr(C)]
+struct RuleNames {
+    rules: *const c_char,
+    model: *const c_char,
+    layout: *const c_char,
+    variant: *const c_char,
+    options: *const c_char,
+}
+
+macro_rules! xkb_functions {
+    ($($name:ident: fn($($arg:ty),*) $(-> $ret:ty)?;)*) => {
+        struct XkbLib {
+            $($name: unsafe extern "C" fn($($arg),*) $(-> $ret)?,)*
+        }
+
+        impl XkbLib {
+            // The library stays loaded for the rest of the process
+            fn load() -> Result<Self, String> {
+                let handle = unsafe {
+                    libc::dlopen(c"libxkbcommon.so.0".as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL)
+                };
+                if handle.is_null() {
+                    return Err(dlerror());
+                }
+
+                Ok(Self {
+                    $($name: {
+                        let name = concat!(stringify!($name), "\0");
+                        let symbol = unsafe { libc::dlsym(handle, name.as_ptr() as *const c_char) };
+                        if symbol.is_null() {
+                            return Err(dlerror());
+                        }
+                        unsafe {
+                            std::mem::transmute::<*mut c_void, unsafe extern "C" fn($($arg),*) $(-> $ret)?>(
+                                symbol,
+                            )
+                        }
+                    },)*
+                })
+            }
+        }
+    };
+}
+
+xkb_functions! {
+    xkb_context_new: fn(c_int) -> *mut c_void;
+    xkb_context_unref: fn(*mut c_void);
+    xkb_keymap_new_from_names: fn(*mut c_void, *const RuleNames, c_int) -> *mut c_void;
+    xkb_keymap_new_from_string: fn(*mut c_void, *const c_char, c_int, c_int) -> *mut c_void;
+    xkb_keymap_unref: fn(*mut c_void);
+    xkb_keymap_num_layouts: fn(*mut c_void) -> u32;
+    xkb_keymap_layout_get_name: fn(*mut c_void, u32) -> *const c_char;
+    xkb_keymap_key_get_syms_by_level: fn(*mut c_void, u32, u32, u32, *mut *const u32) -> c_int;
+    xkb_state_new: fn(*mut c_void) -> *mut c_void;
+    xkb_state_unref: fn(*mut c_void);
+    xkb_state_update_key: fn(*mut c_void, u32, c_int) -> c_int;
+    xkb_state_update_mask: fn(*mut c_void, u32, u32, u32, u32, u32, u32) -> c_int;
+    xkb_state_serialize_layout: fn(*mut c_void, c_int) -> u32;
+    xkb_keysym_to_utf8: fn(u32, *mut c_char, usize) -> c_int;
+}
+
+fn dlerror() -> String {
+    let error = unsafe { libc::dlerror() };
+    if error.is_null() {
+        "unknown dlopen error".to_string()
+    } else {
+        unsafe { CStr::from_ptr(error) }
+            .to_string_lossy()
+            .to_string()
+    }
+}
+
+pub struct XkbLayout {
+    lib: XkbLib,
+    context: *mut c_void,
+    keymap: *mut c_void,
+    state: *mut c_void,
+    // Index of the active layout (xkb "group") in the keymap
+    layout: u32,
+}
+
+impl XkbLayout {
+    // `keymap_file` when set, the rule names otherwise. Unset names fall back
+    // to XKB_DEFAULT_* and then to xkbcommon's defaults (us).
+    pub fn new(config: &XkbConfig) -> Result<Self, String> {
+        match &config.keymap_file {
+            Some(path) => Self::from_keymap_file(path),
+            None => Self::from_names(config),
+        }
+    }
+
+    // e.g. layout "us", variant "colemak"; comma-separated for several layouts
+    pub fn from_names(config: &XkbConfig) -> Result<Self, String> {
+        let to_cstring = |value: &Option<String>| -> Result<Option<CString>, String> {
+            value
+                .as_deref()
+                .map(CString::new)
+                .transpose()
+                .map_err(|error| error.to_string())
+        };
+        let rules = to_cstring(&config.rules)?;
+        let model = to_cstring(&config.model)?;
+        let layout = to_cstring(&config.layout)?;
+        let variant = to_cstring(&config.variant)?;
+        let options = to_cstring(&config.options)?;
+        let as_ptr =
+            |value: &Option<CString>| value.as_ref().map_or(std::ptr::null(), |v| v.as_ptr());
+        let names = RuleNames {
+            rules: as_ptr(&rules),
+            model: as_ptr(&model),
+            layout: as_ptr(&layout),
+            variant: as_ptr(&variant),
+            options: as_ptr(&options),
+        };
+
+        Self::compile(|lib, context| unsafe { (lib.xkb_keymap_new_from_names)(context, &names, 0) })
+    }
+
+    // A keymap as written by `xkbcomp -xkb`
+    pub fn from_keymap_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("{}: {}", path.display(), error))?;
+        let text = CString::new(text).map_err(|error| error.to_string())?;
+
+        Self::compile(|lib, context| unsafe {
+            (lib.xkb_keymap_new_from_string)(context, text.as_ptr(), XKB_KEYMAP_FORMAT_TEXT_V1, 0)
+        })
+    }
+
+    fn compile(
+        new_keymap: impl FnOnce(&XkbLib, *mut c_void) -> *mut c_void,
+    ) -> Result<Self, String> {
+        let lib = XkbLib::load()?;
+
+        let context = unsafe { (lib.xkb_context_new)(0) };
+        if context.is_null() {
+            return Err("failed to create xkb context".to_string());
+        }
+        let keymap = new_keymap(&lib, context);
+        if keymap.is_null() {
+            unsafe { (lib.xkb_context_unref)(context) };
+            return Err("failed to compile keymap".to_string());
+        }
+        let state = unsafe { (lib.xkb_state_new)(keymap) };
+        if state.is_null() {
+            unsafe {
+                (lib.xkb_keymap_unref)(keymap);
+                (lib.xkb_context_unref)(context);
+            }
+            return Err("failed to create xkb state".to_string());
+        }
+
+        Ok(Self {
+            lib,
+            context,
+            keymap,
+            state,
+            layout: 0,
+        })
+    }
+
+    // e.g. "English (Colemak)"
+    pub fn layout_name(&self) -> String {
+        let name = unsafe { (self.lib.xkb_keymap_layout_get_name)(self.keymap, self.layout) };
+        if name.is_null() {
+            return String::new();
+        }
+        unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .to_string()
+    }
+
+    pub fn num_layouts(&self) -> u32 {
+        unsafe { (self.lib.xkb_keymap_num_layouts)(self.keymap) }
+    }
+
+    // The character `key` types on `layer` in the active layout. Shift and
+    // AltGr are shift levels 2 and 3, which is what most key types use them
+    // for. `None` for keys without a keysym there or one that has no
+    // printable character.
+    pub fn label(&self, key: PhysicalKey, layer: Layer) -> Option<String> {
+        let keycode = key.evdev() as u32 + EVDEV_OFFSET;
+        let mut syms: *const u32 = std::ptr::null();
+        let count = unsafe {
+            (self.lib.xkb_keymap_key_get_syms_by_level)(
+                self.keymap,
+                keycode,
+                self.layout,
+                layer as u32,
+                &mut syms,
+            )
+        };
+        if count < 1 || syms.is_null() {
+            return None;
+        }
+
+        let mut buffer = [0 as c_char; 16];
+        let sym = unsafe { *syms };
+        let len = unsafe { (self.lib.xkb_keysym_to_utf8)(sym, buffer.as_mut_ptr(), buffer.len()) };
+        if len <= 1 {
+            return None;
+        }
+        let label = unsafe { CStr::from_ptr(buffer.as_ptr()) }
+            .to_string_lossy()
+            .to_string();
+        // Esc, Tab, Enter and Backspace "type" control characters
+        if label.chars().any(char::is_control) {
+            return None;
+        }
+        Some(label)
+    }
+
+    // Non-Latin when most letter keys type something other than a Latin
+    // letter, e.g. the Russian layout
+    pub fn kind(&self) -> LayoutKind {
+        let letter_keys = PhysicalKey::all().filter(|key| {
+            let name = key.name();
+            name.len() == 1 && name.chars().all(|c| c.is_ascii_alphabetic())
+        });
+        let (mut latin, mut other) = (0, 0);
+        for key in letter_keys {
+            match self.label(key, Layer::Base) {
+                Some(label) if label.chars().all(|c| c.is_ascii_alphabetic()) => latin += 1,
+                Some(_) => other += 1,
+                None => {}
+            }
+        }
+        if other > latin {
+            LayoutKind::NonLatin
+        } else {
+            LayoutKind::Latin
+        }
+    }
+
+    // Feed a key event (Linux key code). Returns true when it switched the
+    // active layout.
+    pub fn key_event(&mut self, code: u16, pressed: bool) -> bool {
+        let direction = if pressed { XKB_KEY_DOWN } else { XKB_KEY_UP };
+        let changed = unsafe {
+            (self.lib.xkb_state_update_key)(self.state, code as u32 + EVDEV_OFFSET, direction)
+        };
+        if changed & XKB_STATE_LAYOUT_EFFECTIVE == 0 {
+            return false;
+        }
+
+        let layout = unsafe {
+            (self.lib.xkb_state_serialize_layout)(self.state, XKB_STATE_LAYOUT_EFFECTIVE)
+        };
+        if layout == self.layout {
+            return false;
+        }
+        self.layout = layout;
+        true
+    }
+
+    // Forget every held key, e.g. a modifier whose release was missed, while
+    // staying on the active layout
+    pub fn reset(&mut self) {
+        let state = unsafe { (self.lib.xkb_state_new)(self.keymap) };
+        if state.is_null() {
+            log::warn!("Failed to create xkb state, keeping the old one");
+            return;
+        }
+        unsafe {
+            (self.lib.xkb_state_unref)(self.state);
+            (self.lib.xkb_state_update_mask)(state, 0, 0, 0, 0, 0, self.layout);
+        }
+        self.state = state;
+    }
+
+    // Write the layout name and key labels to shared memory. Returns whether
+    // that switched layouts.
+    pub fn publish(&self, state: &mut SharedState) -> bool {
+        let mut labels = LayoutLabels::new();
+        labels.set_name(&self.layout_name());
+        labels.set_kind(self.kind());
+        for key in PhysicalKey::all() {
+            for layer in [Layer::Base, Layer::Shift, Layer::AltGr] {
+                if let Some(label) = self.label(key, layer) {
+                    labels.set_label(key, layer, &label);
+                }
+            }
+        }
+        state.layout.publish(&labels)
+    }
+}
+
+impl Drop for XkbLayout {
+    fn drop(&mut self) {
+        unsafe {
+            (self.lib.xkb_state_unref)(self.state);
+            (self.lib.xkb_keymap_unref)(self.keymap);
+            (self.lib.xkb_context_unref)(self.context);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Full keymap dumps (evdev rules, pc105) in `testdata/`, so the labels
+    // don't depend on the xkb data installed here. Only libxkbcommon itself is
+    // needed; without it the tests fail with the loader's error. Regenerate
+    // with e.g. `xkbcli compile-keymap --layout us --variant colemak`.
+    fn load(name: &str) -> Result<XkbLayout, String> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join(format!("{}.xkb", name));
+        XkbLayout::from_keymap_file(&path)
+    }
+
+    fn base(layout: &XkbLayout, key: PhysicalKey) -> Option<String> {
+        layout.label(key, Layer::Base)
+    }
+
+    const KEY_LEFTALT: u16 = 56;
+    const KEY_LEFTSHIFT: u16 = 42;
+
+    #[test]
+    fn us_labels() -> Result<(), String> {
+        let layout = load("us")?;
+        assert_eq!(layout.layout_name(), "English (US)");
+        assert_eq!(layout.num_layouts(), 1);
+        assert_eq!(layout.kind(), LayoutKind::Latin);
+        assert_eq!(base(&layout, PhysicalKey::J).as_deref(), Some("j"));
+        assert_eq!(
+            layout.label(PhysicalKey::J, Layer::Shift).as_deref(),
+            Some("J")
+        );
+        assert_eq!(
+            layout.label(PhysicalKey::Digit2, Layer::Shift).as_deref(),
+            Some("@")
+        );
+        // Keys that type nothing printable have no label
+        assert_eq!(base(&layout, PhysicalKey::Escape), None);
+        assert_eq!(base(&layout, PhysicalKey::Enter), None);
+        Ok(())
+    }
+
+    #[test]
+    fn colemak_moves_letters() -> Result<(), String> {
+        let layout = load("us-colemak")?;
+        assert_eq!(layout.layout_name(), "English (Colemak)");
+        assert_eq!(base(&layout, PhysicalKey::J).as_deref(), Some("n"));
+        assert_eq!(base(&layout, PhysicalKey::Semicolon).as_deref(), Some("o"));
+        Ok(())
+    }
+
+    #[test]
+    fn german_labels() -> Result<(), String> {
+        let layout = load("de")?;
+        assert_eq!(layout.layout_name(), "German");
+        assert_eq!(layout.kind(), LayoutKind::Latin);
+        assert_eq!(base(&layout, PhysicalKey::Y).as_deref(), Some("z"));
+        assert_eq!(base(&layout, PhysicalKey::Semicolon).as_deref(), Some("ö"));
+        assert_eq!(
+            layout.label(PhysicalKey::Q, Layer::AltGr).as_deref(),
+            Some("@")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn japanese_labels() -> Result<(), String> {
+        let layout = load("jp")?;
+        assert_eq!(layout.layout_name(), "Japanese");
+        // The keymap types kana only through an input method
+        assert_eq!(layout.kind(), LayoutKind::Latin);
+        assert_eq!(base(&layout, PhysicalKey::J).as_deref(), Some("j"));
+        assert_eq!(
+            layout.label(PhysicalKey::Digit2, Layer::Shift).as_deref(),
+            Some("\"")
+        );
+        assert_eq!(base(&layout, PhysicalKey::Equal).as_deref(), Some("^"));
+        Ok(())
+    }
+
+    #[test]
+    fn switch_keys_change_the_layout() -> Result<(), String> {
+        let mut layout = load("us-de-alt-shift-toggle")?;
+        assert_eq!(layout.num_layouts(), 2);
+        assert_eq!(layout.layout_name(), "English (US)");
+
+        assert!(!layout.key_event(KEY_LEFTALT, true));
+        assert!(layout.key_event(KEY_LEFTSHIFT, true));
+        assert_eq!(layout.layout_name(), "German");
+        assert_eq!(base(&layout, PhysicalKey::Y).as_deref(), Some("z"));
+        assert!(!layout.key_event(KEY_LEFTSHIFT, false));
+        assert!(!layout.key_event(KEY_LEFTALT, false));
+
+        // Toggles back
+        layout.key_event(KEY_LEFTALT, true);
+        assert!(layout.key_event(KEY_LEFTSHIFT, true));
+        assert_eq!(layout.layout_name(), "English (US)");
+        Ok(())
+    }
+
+    #[test]
+    fn reset_forgets_held_keys_but_not_the_layout() -> Result<(), String> {
+        let mut layout = load("us-de-alt-shift-toggle")?;
+        layout.key_event(KEY_LEFTALT, true);
+        layout.key_event(KEY_LEFTSHIFT, true);
+        assert_eq!(layout.layout_name(), "German");
+
+        // Both releases were missed
+        layout.reset();
+        assert_eq!(layout.layout_name(), "German");
+        // Alt is no longer held, so Shift alone doesn't switch
+        assert!(!layout.key_event(KEY_LEFTSHIFT, true));
+        assert!(!layout.key_event(KEY_LEFTSHIFT, false));
+        assert_eq!(layout.layout_name(), "German");
+        // The locked layout survived in the new state too
+        layout.key_event(KEY_LEFTALT, true);
+        assert!(layout.key_event(KEY_LEFTSHIFT, true));
+        assert_eq!(layout.layout_name(), "English (US)");
+        Ok(())
+    }
+}
@@ -404,7 +404,7 @@ class KeyMonitor: ObservableObject {
         for (position, keyCode) in keyPositions {
             let label = getCharacterForKeyCode(keyCode: keyCode) ?? position.uppercased()
             print("Setting key label for position \(position) (keyCode \(keyCode)): \(label)")
-            set_key_label(position, label)
+            set_key_label_for_keycode(UInt16(keyCode), 0, label)
         }
     }
     
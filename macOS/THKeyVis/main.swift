@@ -8,8 +8,9 @@ import Cocoa
 var currentInputSource: TISInputSource?
 var lastLayoutName: String = ""
 
-// Helper function to get character for a specific key code in current layout
-func getCharacterForKeyCode(keyCode: Int, inputSource: TISInputSource) -> String? {
+// Helper function to get character for a specific key code in current layout.
+// `modifiers` are Carbon modifier flags such as `shiftKey` (0 for none).
+func getCharacterForKeyCode(keyCode: Int, inputSource: TISInputSource, modifiers: Int = 0) -> String? {
     // Get the keyboard layout data
     guard let layoutDataRef = TISGetInputSourceProperty(inputSource, kTISPropertyUnicodeKeyLayoutData) else {
         return nil
@@ -26,7 +27,7 @@ func getCharacterForKeyCode(keyCode: Int, inputSource: TISInputSource) -> String
         UnsafePointer<UCKeyboardLayout>(OpaquePointer(keyboardLayoutPtr)),
         UInt16(keyCode),
         UInt16(kUCKeyActionDisplay),
-        0, // no modifier keys
+        UInt32((modifiers >> 8) & 0xFF), // UCKeyTranslate wants the flags shifted down a byte
         UInt32(LMGetKbdType()),
         OptionBits(kUCKeyTranslateNoDeadKeysBit),
         &deadKeyState,
@@ -42,28 +43,55 @@ func getCharacterForKeyCode(keyCode: Int, inputSource: TISInputSource) -> String
     return String(utf16CodeUnits: unicodeString, count: actualStringLength)
 }
 
+// Kind of input source for the warning badge (matches Rust's LayoutKind):
+// 0 = Latin, 1 = non-Latin layout, 2 = input method composing text
+func getLayoutKind(inputSource: TISInputSource) -> UInt8 {
+    var asciiCapable = true
+    if let asciiRef = TISGetInputSourceProperty(inputSource, kTISPropertyInputSourceIsASCIICapable) {
+        asciiCapable = CFBooleanGetValue(Unmanaged<CFBoolean>.fromOpaque(asciiRef).takeUnretainedValue())
+    }
+    
+    var isInputMethod = false
+    if let typeRef = TISGetInputSourceProperty(inputSource, kTISPropertyInputSourceType) {
+        let type = Unmanaged<CFString>.fromOpaque(typeRef).takeUnretainedValue()
+        isInputMethod = CFEqual(type, kTISTypeKeyboardInputMode) || CFEqual(type, kTISTypeKeyboardInputMethodWithoutModes)
+    }
+    
+    // An input method in an ASCII mode (e.g. Japanese "Romaji") passes keys through
+    if isInputMethod && !asciiCapable {
+        return 2
+    }
+    return asciiCapable ? 0 : 1
+}
+
 // Helper function to update Rust with current layout information
 func updateRustLayoutInfo(name: String, inputSource: TISInputSource) {
-    // Send layout name to Rust
-    set_layout_name(name)
-    
-    // Update key labels for each monitored key position
-    let keyPositions = [
-        ("a", 0),   // A key (keycode 0)
-        ("s", 1),   // S key (keycode 1) 
-        ("d", 2),   // D key (keycode 2)
-        ("f", 3),   // F key (keycode 3)
-        ("j", 38),  // J key (keycode 38)
-        ("k", 40),  // K key (keycode 40)
-        ("l", 37),  // L key (keycode 37)
-        (";", 41),  // ; key (keycode 41)
-    ]
-    
-    for (position, keyCode) in keyPositions {
-        let label = getCharacterForKeyCode(keyCode: keyCode, inputSource: inputSource) ?? position.uppercased()
-        print("Setting key label for position \(position) (keyCode \(keyCode)): \(label)")
-        set_key_label(position, label)
+    // Input methods have no key layout data; label keys from the keyboard
+    // layout they type through instead
+    let layoutSource = TISGetInputSourceProperty(inputSource, kTISPropertyUnicodeKeyLayoutData) != nil
+        ? inputSource
+        : TISCopyCurrentKeyboardLayoutInputSource().takeRetainedValue()
+    let kind = getLayoutKind(inputSource: inputSource)
+    
+    // Every key Rust tracks, as virtual key codes
+    let keyCount = get_monitored_keycodes(nil, 0)
+    var keyCodes = [UInt16](repeating: 0, count: keyCount)
+    _ = get_monitored_keycodes(&keyCodes, keyCount)
+    
+    // Keys that don't type a character (Esc, arrows, ...) get no label and
+    // Rust draws their own name. Layers: base, Shift, AltGr (Option).
+    let layers = [0, shiftKey, optionKey].map { modifiers in
+        keyCodes.map { keyCode in
+            getCharacterForKeyCode(keyCode: Int(keyCode), inputSource: layoutSource, modifiers: modifiers) ?? ""
+        }
     }
+    print("Setting \(layers[0].filter { !$0.isEmpty }.count) key labels for layout \(name) (kind \(kind))")
+    
+    // Name and labels in one call, so the window never shows half of a layout
+    let cLayers = layers.map { labels in labels.map { strdup($0) } }
+    defer { cLayers.forEach { $0.forEach { free($0) } } }
+    let layerPointers = cLayers.map { labels in labels.map { UnsafePointer($0) } }
+    set_keyboard_layout(name, kind, keyCodes, layerPointers[0], layerPointers[1], layerPointers[2], keyCount)
 }
 
 // Function to update current input source and notify Rust
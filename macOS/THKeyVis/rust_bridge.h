@@ -3,6 +3,7 @@
 
 #include <stdbool.h>
 #include <stddef.h>
+#include <stdint.h>
 
 // Function pointer type for permission monitoring callback
 typedef void (*permission_monitoring_callback_t)(void);
@@ -15,8 +16,11 @@ extern void set_accessibility_permission(bool has_permission);
 extern bool get_accessibility_permission(void);
 
 // Keyboard layout management functions
+// Keys are identified by macOS virtual key codes (kVK_*)
 extern void set_layout_name(const char *name);
-extern void set_key_label(const char *position, const char *label);
+extern void set_key_label_for_keycode(uint16_t keycode, uint8_t layer, const char *label); // layer: 0 = base, 1 = Shift, 2 = AltGr (Option)
+extern void set_keyboard_layout(const char *name, uint8_t kind, const uint16_t *keycodes, const char *const *base_labels, const char *const *shift_labels, const char *const *altgr_labels, size_t count); // Name, kind (0 = Latin, 1 = non-Latin, 2 = input method) and all labels at once
+extern size_t get_monitored_keycodes(uint16_t *buffer, size_t buffer_size); // Returns the number of keys, writes up to buffer_size
 extern void get_layout_name(char *buffer, size_t buffer_size);
 
 // Application lifecycle management